@@ -0,0 +1,468 @@
+///
+/// Blitz Explorer
+///
+/// Cache of the extracted files. The files are removed by the
+/// last access(LRU) when the cache is over of his max size
+///
+/// Copyright 2019 Luis Fernando Batels <luisfbatels@gmail.com>
+///
+
+use std::fs::{self, File};
+use std::path::PathBuf;
+use std::io::{Read, Write, BufWriter, copy};
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use sled::{Db, Tree};
+
+// Prefix of the files still on writing, and of the temporary files,
+// removed on the start
+const TEMP_PREFIX: &str = "blitze_tmp_";
+
+pub struct Cache {
+    pub dir: String,
+    pub max_size: u64, // bytes
+    db: Db,
+    entries: Arc<Tree>, // name => size + access
+    lru: Arc<Tree>, // access + name => nothing, oldest first
+    size: AtomicU64,
+    hits: AtomicU64,
+    misses: AtomicU64,
+    evictions: AtomicU64,
+    lock: Mutex<()>
+}
+
+pub struct CacheStats {
+    pub entries: usize,
+    pub size: u64,
+    pub max_size: u64,
+    pub hits: u64,
+    pub misses: u64,
+    pub evictions: u64
+}
+
+impl Cache {
+
+    // Start the cache on the dir, with the files of the last
+    // executions
+    pub fn new(db: Db, dir: String, max_size: u64) -> Cache {
+
+        if let Err(e) = fs::create_dir_all(&dir) {
+            error!("Can't create the cache dir {}: {}", dir, e);
+        }
+
+        let cache = Cache {
+            dir: dir,
+            max_size: max_size,
+            entries: db.open_tree("cache")
+                .expect("Can't open the cache tree"),
+            lru: db.open_tree("cache_lru")
+                .expect("Can't open the cache lru tree"),
+            db: db,
+            size: AtomicU64::new(0),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+            evictions: AtomicU64::new(0),
+            lock: Mutex::new(())
+        };
+
+        let mut size = 0;
+
+        for val in cache.entries.iter() {
+
+            let (_key, uval) = val.expect("Error on get the val of cache entry");
+
+            size = size + Cache::bytes_to_u64(&uval[..8]);
+        }
+
+        cache.size.store(size, Ordering::SeqCst);
+
+        // Files of writings interrupted
+        if let Ok(files) = fs::read_dir(&cache.dir) {
+            for file in files.filter_map(|file| file.ok()) {
+                if file.file_name().to_string_lossy().starts_with(TEMP_PREFIX) {
+                    let _ = fs::remove_file(file.path());
+                }
+            }
+        }
+
+        cache
+    }
+
+    // Path of the cached file
+    pub fn path(&self, name: &str) -> PathBuf {
+        PathBuf::from(format!("{}/{}", self.dir, name))
+    }
+
+    // New path for a temporary file on the dir of cache
+    pub fn temp_path(&self) -> PathBuf {
+
+        let id = self.db.generate_id()
+            .expect("Error on generate the id of temporary file");
+
+        self.path(&format!("{}{}", TEMP_PREFIX, id))
+    }
+
+    // Return if the file is on cache
+    pub fn contains(&self, name: &str) -> bool {
+        self.entries.get(name.as_bytes()).unwrap_or(None).is_some()
+    }
+
+    // Open the cached file, if exists and has the size expected
+    pub fn get(&self, name: &str, size: u64) -> Option<File> {
+
+        if !self.contains(name) {
+            self.misses.fetch_add(1, Ordering::SeqCst);
+            return None;
+        }
+
+        let file = File::open(self.path(name))
+            .and_then(|file| file.metadata().map(|meta| (file, meta.len())));
+
+        match file {
+            Ok((file, len)) if len == size => {
+                self.hits.fetch_add(1, Ordering::SeqCst);
+                self.touch(name);
+
+                Some(file)
+            },
+            _ => {
+                // Changed or removed outside of the service
+                warn!("Invalid cache file {}. Removing...", name);
+
+                self.misses.fetch_add(1, Ordering::SeqCst);
+                self.remove(name);
+
+                None
+            }
+        }
+    }
+
+    // Save the content on cache and return the cached file. The content
+    // is written on a temporary file, so a partial file is never used
+    pub fn put<R: Read>(&self, name: &str, size: u64, content: &mut R) -> Option<File> {
+
+        let temp = self.temp_path();
+
+        let file = File::create(&temp);
+
+        if let Err(e) = file {
+            error!("Can't create the cache file {}: {}", temp.display(), e);
+            return None;
+        }
+
+        let mut writer = BufWriter::new(file.unwrap());
+
+        let written = copy(content, &mut writer);
+
+        if written.is_err() || writer.flush().is_err() {
+            error!("Error on write the cache file {}", temp.display());
+            let _ = fs::remove_file(&temp);
+            return None;
+        }
+
+        let written = written.unwrap();
+
+        if written != size {
+            error!("Incomplete content for the cache file {}: {} of {} bytes", name, written, size);
+            let _ = fs::remove_file(&temp);
+            return None;
+        }
+
+        let path = self.path(name);
+
+        if let Err(e) = fs::rename(&temp, &path) {
+            error!("Can't move the cache file {}: {}", path.display(), e);
+            let _ = fs::remove_file(&temp);
+            return None;
+        }
+
+        {
+            let _writing = self.lock.lock()
+                .expect("Error on lock the cache");
+
+            if let Ok(Some(old)) = self.entries.get(name.as_bytes()) {
+                self.forget(name, &old);
+            }
+
+            self.set(name, written, self.next_access());
+
+            self.evict(name);
+        }
+
+        match File::open(&path) {
+            Ok(file) => Some(file),
+            Err(e) => {
+                error!("Can't open the cache file {}: {}", path.display(), e);
+                None
+            }
+        }
+    }
+
+    // Remove the file of cache
+    pub fn remove(&self, name: &str) {
+
+        let _writing = self.lock.lock()
+            .expect("Error on lock the cache");
+
+        if let Ok(Some(uval)) = self.entries.get(name.as_bytes()) {
+            self.forget(name, &uval);
+        }
+
+        let _ = fs::remove_file(self.path(name));
+    }
+
+    // Remove all files of cache with the prefix on his names
+    pub fn remove_prefix(&self, prefix: &str) {
+
+        let names: Vec<String> = self.entries.scan(prefix.as_bytes())
+            .filter_map(|val| val.ok())
+            .map(|(key, _val)| String::from_utf8_lossy(&key).to_string())
+            .take_while(|name| name.starts_with(prefix))
+            .collect();
+
+        for name in names {
+            self.remove(&name);
+        }
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        CacheStats {
+            entries: self.entries.iter().count(),
+            size: self.size.load(Ordering::SeqCst),
+            max_size: self.max_size,
+            hits: self.hits.load(Ordering::SeqCst),
+            misses: self.misses.load(Ordering::SeqCst),
+            evictions: self.evictions.load(Ordering::SeqCst)
+        }
+    }
+
+    // Remove the files with the oldest access until the cache be
+    // smaller than his max size. The current file is keeped
+    fn evict(&self, current: &str) {
+
+        while self.size.load(Ordering::SeqCst) > self.max_size {
+
+            let oldest = self.lru.iter()
+                .filter_map(|val| val.ok())
+                .map(|(key, _val)| key.to_vec())
+                .find(|key| &key[8..] != current.as_bytes());
+
+            let key = match oldest {
+                Some(key) => key,
+                None => return
+            };
+
+            let name = String::from_utf8_lossy(&key[8..]).to_string();
+
+            match self.entries.get(name.as_bytes()) {
+                Ok(Some(uval)) => self.forget(&name, &uval),
+                _ => {
+                    // Access without entry, of a old crash
+                    self.lru.del(&key)
+                        .expect("Error on remove the cache lru");
+                    continue;
+                }
+            }
+
+            if let Err(e) = fs::remove_file(self.path(&name)) {
+                warn!("Can't remove the cache file {}: {}", name, e);
+            }
+
+            self.evictions.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    // Update the last access of the file
+    fn touch(&self, name: &str) {
+
+        let _writing = self.lock.lock()
+            .expect("Error on lock the cache");
+
+        if let Ok(Some(uval)) = self.entries.get(name.as_bytes()) {
+
+            let size = Cache::bytes_to_u64(&uval[..8]);
+
+            self.forget(name, &uval);
+            self.set(name, size, self.next_access());
+        }
+    }
+
+    fn set(&self, name: &str, size: u64, access: u64) {
+
+        let mut val = size.to_be_bytes().to_vec();
+        val.extend_from_slice(&access.to_be_bytes());
+
+        self.entries.set(name.as_bytes(), val)
+            .expect("Error on set the cache entry");
+
+        self.lru.set(Cache::lru_key(name, access), vec![])
+            .expect("Error on set the cache lru");
+
+        self.size.fetch_add(size, Ordering::SeqCst);
+    }
+
+    // Remove the entry of the tracking, but not his file
+    fn forget(&self, name: &str, uval: &[u8]) {
+
+        let size = Cache::bytes_to_u64(&uval[..8]);
+        let access = Cache::bytes_to_u64(&uval[8..16]);
+
+        self.entries.del(name.as_bytes())
+            .expect("Error on remove the cache entry");
+
+        self.lru.del(Cache::lru_key(name, access))
+            .expect("Error on remove the cache lru");
+
+        let current = self.size.load(Ordering::SeqCst);
+        self.size.store(current.saturating_sub(size), Ordering::SeqCst);
+    }
+
+    fn next_access(&self) -> u64 {
+        self.db.generate_id()
+            .expect("Error on generate the access of cache file")
+    }
+
+    fn lru_key(name: &str, access: u64) -> Vec<u8> {
+
+        let mut key = access.to_be_bytes().to_vec();
+        key.extend_from_slice(name.as_bytes());
+
+        key
+    }
+
+    fn bytes_to_u64(val: &[u8]) -> u64 {
+
+        let mut n = [0u8; 8];
+        n.copy_from_slice(&val[..8]);
+
+        u64::from_be_bytes(n)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use std::env;
+    use std::fs;
+    use std::path::PathBuf;
+    use std::process;
+
+    use sled::Db;
+
+    use super::Cache;
+
+    fn start(name: &str, max_size: u64) -> (PathBuf, Db, Cache) {
+
+        let dir = env::temp_dir().join(format!("blitze_cache_{}_{}", name, process::id()));
+        let _ = fs::remove_dir_all(&dir);
+
+        let db = Db::start_default(dir.join("db")).unwrap();
+        let cache = Cache::new(db.clone(), dir.join("files").to_str().unwrap().to_string(), max_size);
+
+        (dir, db, cache)
+    }
+
+    fn put(cache: &Cache, name: &str, size: usize) {
+        assert!(cache.put(name, size as u64, &mut &vec![b'x'; size][..]).is_some());
+    }
+
+    #[test]
+    fn evict_the_least_recently_used() {
+
+        let (dir, db, cache) = start("lru", 30);
+
+        put(&cache, "a", 10);
+        put(&cache, "b", 10);
+        put(&cache, "c", 10);
+
+        // The a is used again, so the b is the oldest
+        assert!(cache.get("a", 10).is_some());
+
+        put(&cache, "d", 10);
+
+        assert!(cache.contains("a"));
+        assert!(!cache.contains("b"));
+        assert!(!cache.path("b").exists());
+        assert!(cache.contains("c"));
+        assert!(cache.contains("d"));
+
+        let stats = cache.stats();
+        assert_eq!(stats.entries, 3);
+        assert_eq!(stats.size, 30);
+        assert_eq!(stats.evictions, 1);
+        assert_eq!(stats.hits, 1);
+
+        // A file bigger than the cache is keeped alone
+        put(&cache, "e", 50);
+
+        assert!(cache.contains("e"));
+        assert_eq!(cache.stats().entries, 1);
+        assert_eq!(cache.stats().size, 50);
+        assert_eq!(cache.stats().evictions, 4);
+
+        // The totals are the same after a restart
+        drop(cache);
+        let cache = Cache::new(db, dir.join("files").to_str().unwrap().to_string(), 30);
+
+        assert_eq!(cache.stats().entries, 1);
+        assert_eq!(cache.stats().size, 50);
+
+        drop(cache);
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn replace_and_check_the_size() {
+
+        let (dir, _db, cache) = start("size", 100);
+
+        put(&cache, "a", 10);
+        put(&cache, "a", 20);
+
+        assert_eq!(cache.stats().entries, 1);
+        assert_eq!(cache.stats().size, 20);
+
+        // The file with other size is invalid
+        assert!(cache.get("a", 10).is_none());
+        assert!(!cache.contains("a"));
+        assert!(!cache.path("a").exists());
+        assert_eq!(cache.stats().size, 0);
+        assert_eq!(cache.stats().misses, 1);
+
+        // The incomplete content is not cached
+        assert!(cache.put("b", 20, &mut &vec![b'x'; 10][..]).is_none());
+        assert!(!cache.contains("b"));
+        assert_eq!(cache.stats().size, 0);
+
+        drop(cache);
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn remove_by_prefix() {
+
+        let (dir, _db, cache) = start("prefix", 100);
+
+        put(&cache, "tar_a_1", 10);
+        put(&cache, "tar_a_2", 10);
+        put(&cache, "tar_b_1", 10);
+        put(&cache, "sha256_1", 10);
+
+        cache.remove_prefix("tar_a_");
+
+        assert!(!cache.contains("tar_a_1"));
+        assert!(!cache.contains("tar_a_2"));
+        assert!(!cache.path("tar_a_1").exists());
+        assert!(cache.contains("tar_b_1"));
+        assert!(cache.contains("sha256_1"));
+
+        let stats = cache.stats();
+        assert_eq!(stats.entries, 2);
+        assert_eq!(stats.size, 20);
+        assert_eq!(stats.evictions, 0);
+
+        drop(cache);
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
@@ -6,29 +6,72 @@
 /// Copyright 2019 Luis Fernando Batels <luisfbatels@gmail.com>
 ///
 
-use std::fs::File;
+use std::fs::{self, File};
 use std::path::{Path, PathBuf};
-use std::io::{BufReader, BufWriter, copy};
-use std::sync::Arc;
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Seek, SeekFrom, Write, copy, sink};
+use std::sync::{Arc, Mutex, RwLock};
 use std::str;
-use std::collections::{HashMap, LinkedList};
+use std::time::UNIX_EPOCH;
+use std::collections::{BTreeSet, HashMap, LinkedList};
 
-use flate2::read::GzDecoder;
-use tar::Archive;
+use tar::{Archive, EntryType};
 use sled::{Db, Tree};
 
 use super::file::File as IndexedFile;
-use super::file::FileTar;
+use super::file::{FileTar, FileKind};
+use super::gzindex::GzIndexer;
+use super::decoder::{Compression, is_tar};
+use super::zipfile;
+use super::digest::{self, DigestReader};
+use super::cache::Cache;
+use super::index::SearchIndex;
+use super::contents::{self, Capture};
+
+// Max of links followed for resolve a file, like the SYMLOOP_MAX
+const MAX_LINKS: usize = 40;
+
+// Bytes readed of a member for know if he is a archive. A block of
+// bzip2 needs until 900K for decompress the tar header
+const NESTED_HEAD: u64 = 1048576;
+
+// Names of the members indexed as archives. Others formats are zip
+// files too, like the .xlsx, .docx and .jar, but they are documents
+const ARCHIVE_EXTENSIONS: [&str; 11] = [".zip", ".tar", ".tar.gz", ".tgz", ".tar.bz2", ".tbz", ".tbz2", ".tar.xz", ".txz", ".tar.zst", ".tzst"];
+
+// Full path of the folders indexed => his ino and his childs, with the
+// name and the ino of each child
+type Parents = HashMap<String, (u64, LinkedList<(String, u64)>)>;
 
 pub struct Catalog {
    pub db: Db,
-   pub cache_extract: String
+   pub cache: Cache, // extracted files
+   pub index: SearchIndex, // for the searches
+   pub nested_depth: usize, // max depth of archives inside archives, 0 for don't index them
+   pub hash_contents: bool, // calculate the SHA-256 of the files while indexing
+   pub contents_max: u64, // max size of the text files with the words indexed, 0 for don't index them
+   locks: Mutex<HashMap<String, Arc<RwLock<()>>>>, // full path of tar => lock of his indexed content
+   extracting: Mutex<HashMap<String, Arc<Mutex<()>>>>, // cache key => lock of the extraction of file
+   ino_lock: Mutex<()>
 }
 
 impl Catalog {
 
+    pub fn new(db: Db, cache: Cache, nested_depth: usize, hash_contents: bool, contents_max: u64) -> Catalog {
+        Catalog {
+            index: SearchIndex::new(&db),
+            db: db,
+            cache: cache,
+            nested_depth: nested_depth,
+            hash_contents: hash_contents,
+            contents_max: contents_max,
+            locks: Mutex::new(HashMap::new()),
+            extracting: Mutex::new(HashMap::new()),
+            ino_lock: Mutex::new(())
+        }
+    }
+
     // Index the content of compressed file
-    pub fn catalog_file(&mut self, path: &Path) -> Option<FileTar> {
+    pub fn catalog_file(&self, path: &Path) -> Option<FileTar> {
 
         info!("Indexing {}...", path.display());
 
@@ -37,323 +80,2118 @@ impl Catalog {
             return None;
         }
 
-        if !FileTar::path_to_string(path, false).ends_with(".tar.gz") {
-            warn!("Is not a tar.gz file {}. Skiping...", path.display());
+        if !zipfile::is_zip(path) && Compression::detect_tar(path).is_none() {
+            warn!("Is not a supported archive {}. Skiping...", path.display());
             return None;
         }
 
         let ftar = FileTar::from_path(path);
-        if self.is_indexed(&ftar) {
+
+        // Indexed by old versions, without the search indexes
+        if self.is_indexed(&ftar) && !self.index.has_archive(&ftar) {
+            self.burn_catalog(&ftar);
+        }
+
+        // Only the indexing of this tar need wait the readers
+        let lock = self.get_lock(&ftar);
+        let _writing = lock.write()
+            .expect("Error on lock the tar for indexing");
+
+        // Indexes without the ino of the tar root are of old versions,
+        // so we index them again
+        if self.is_indexed(&ftar) && self.get_tar_ino(&ftar).is_some() {
             warn!("Already indexed {}. Skiping...", path.display());
             return None;
         }
 
-        let archive = File::open(path);
+        let tree = self.get_tree(&ftar);
 
-        if let Err(e) = archive {
-            error!("Can't open the file {}: {}. Skiping...", path.display(), e);
+        let mut parents_inos: Parents = HashMap::new();
+
+        { // Root dir of tar file
+            let ino = self.get_ino(&ftar, "");
+
+            parents_inos.insert("".to_string(), (ino, LinkedList::new()));
+        }
+
+        // A corrupt archive is not indexed, the part of him already
+        // indexed is removed
+        if !self.index_archive(path, &ftar, "", &tree, &mut parents_inos, 0) {
+            drop(_writing);
+            self.burn_catalog(&ftar);
             return None;
         }
 
-        let buffer_archive = BufReader::new(archive.unwrap());
+        // The childs are indexed by name, for the lookups, and
+        // by position, for the listings
+        for (_parent, inos) in parents_inos {
+            let files = self.get_tree_inos(inos.0);
+            let list = self.get_tree_inos_list(inos.0);
 
-        let decoder = GzDecoder::new(buffer_archive);
-        let buffer_decoder = BufReader::new(decoder);
+            for (position, (name, file)) in inos.1.into_iter().enumerate() {
+                files.set(name.as_bytes(), file.to_string().as_bytes().to_vec())
+                    .expect("Error on set the index tree ino");
 
-        let mut tar = Archive::new(buffer_decoder);
+                list.set((position as u64).to_be_bytes().to_vec(), Catalog::child_to_bytes(&name, file))
+                    .expect("Error on set the list tree ino");
+            }
+        }
 
-        let entries = tar.entries()
-            .expect("Error on get the entries of tar file");
+        // The tar is a child of the root of mount
+        if let Some(ino) = self.get_tar_ino(&ftar) {
+            self.get_tree_inos(1).set(ftar.file_name.as_bytes(), ino.to_string().as_bytes().to_vec())
+                .expect("Error on set the index tree ino");
 
-        let tree = self.get_tree(&ftar);
+            let list = self.get_tree_inos_list(1);
+
+            let position = match list.iter().next_back() {
+                Some(Ok((key, _val))) => Catalog::bytes_to_ino(&key) + 1,
+                _ => 0
+            };
 
-        let mut parents_inos: HashMap<String, (u64, LinkedList<u64>)> = HashMap::new();
+            list.set(position.to_be_bytes().to_vec(), Catalog::child_to_bytes(&ftar.file_name, ino))
+                .expect("Error on set the list tree ino");
+        }
 
-        { // Root dir of tar file
-            let ino = self.get_last_ino() + 1;
+        self.index.set_archive(&ftar);
 
-            parents_inos.insert("".to_string(), (ino, LinkedList::new()));
+        self.db.flush()
+         .expect("Error on flush db");
+
+        info!("Indexing {}...OK", path.display());
+
+        return Some(ftar);
+    }
+
+    // Index the entries of a tar or zip file. The container is the
+    // path of the archive inside of the indexed file, empty for the
+    // indexed file itself
+    fn index_archive(&self, path: &Path, ftar: &FileTar, container: &str, tree: &Tree, parents_inos: &mut Parents, depth: usize) -> bool {
 
-            self.set_last_ino(ino);
+        if zipfile::is_zip(path) {
+            return self.index_zip(path, ftar, container, tree, parents_inos, depth);
         }
 
-        for file in entries {
+        return self.index_tar(path, ftar, container, tree, parents_inos, depth);
+    }
 
-            let header = file
-                .expect("Erro on get the entrie file header")
-                .header()
-                .clone();
+    // Index the entries of a tar file
+    fn index_tar(&self, path: &Path, ftar: &FileTar, container: &str, tree: &Tree, parents_inos: &mut Parents, depth: usize) -> bool {
 
-            let full_path = &header.path()
-                .expect("Can't get the full path");
+        let compression = match Compression::detect_tar(path) {
+            Some(compression) => compression,
+            None => {
+                warn!("Is not a supported archive {}. Skiping...", path.display());
+                return false;
+            }
+        };
+
+        let archive = File::open(path);
+
+        if let Err(e) = archive {
+            error!("Can't open the file {}: {}. Skiping...", path.display(), e);
+            return false;
+        }
+
+        let buffer_archive = BufReader::new(archive.unwrap());
+
+        let decoder: io::Result<Box<dyn Read>> = match compression {
+            // While decompress, we save the checkpoints for the extraction
+            Compression::Gzip => GzIndexer::new(buffer_archive, self.get_tree_seek(ftar, container))
+                .map(|indexer| Box::new(indexer) as Box<dyn Read>),
+            other => other.decoder(path)
+        };
 
-            let ino = self.get_last_ino() + 1;
+        if let Err(e) = decoder {
+            error!("Can't start the decompression of {}: {}. Skiping...", path.display(), e);
+            return false;
+        }
 
-            let full_path_str = FileTar::path_to_string(full_path, true);
-            let file_name_str = FileTar::path_to_string(full_path, false);
+        let buffer_decoder = BufReader::new(decoder.unwrap());
 
-            let mut level = full_path_str.clone().matches("/").count();
+        let mut tar = Archive::new(buffer_decoder);
 
-            if header.entry_type().is_file() {
-                level = level + 1;
+        let entries = match tar.entries() {
+            Ok(entries) => entries,
+            Err(e) => {
+                warn!("Can't read the entries of {}: {}. Skiping...", path.display(), e);
+                return false;
             }
+        };
 
-            let parent_n = full_path_str.rfind(file_name_str.as_str())
-                .expect("Error on get the pos of file name");
-            let parent = &full_path_str.clone()[..parent_n];
+        for file in entries {
+
+            let mut entrie = match file {
+                Ok(entrie) => entrie,
+                Err(e) => {
+                    warn!("Corrupt entry on {}: {}. Skiping...", path.display(), e);
+                    return false;
+                }
+            };
+
+            let header = entrie.header().clone();
+
+            let entry_path = match header.path() {
+                Ok(full_path) => Catalog::entry_path(&full_path),
+                Err(e) => {
+                    warn!("Invalid path of entry on {}: {}. Skiping...", path.display(), e);
+                    return false;
+                }
+            };
 
-            if header.entry_type().is_dir() && !parents_inos.contains_key(&full_path_str) {
-                parents_inos.insert(full_path_str.clone(), (ino, LinkedList::new()));
+            // The root folder, like the ./ of the archives created
+            // from the current folder
+            if entry_path.is_empty() {
+                continue;
             }
 
-            if let Some(parent_list) = parents_inos.get_mut(parent) {
-                parent_list.1.push_back(ino);
+            if !Catalog::is_valid_name(&entry_path) {
+                warn!("Invalid name of entry {} on {}. Skiping...", entry_path, path.display());
+                continue;
             }
 
+            // Like the archives created with `tar -T filelist`
+            self.index_parents(ftar, container, &entry_path, header.mtime().unwrap_or(0), tree, parents_inos);
+
+            let full_path = Path::new(&entry_path);
+
+            let offset = entrie.raw_file_position();
+
+            let is_file = header.entry_type().is_file();
+
+            // The hardlinks have the content of his target
+            let is_hardlink = header.entry_type() == EntryType::Link;
+
+            // The digest is calculated, and the text captured, while
+            // the content is streaming
+            let capture = self.capture_max(is_file, header.size().unwrap_or(0));
+
+            let (nested, sha256, text) = {
+                let mut content = Capture::new(DigestReader::new(&mut entrie, is_file && self.hash_contents), capture);
+
+                let nested = if is_file && depth < self.nested_depth && Catalog::is_archive_name(&entry_path) {
+                    self.nested_archive(&mut content)
+                } else {
+                    None
+                };
+
+                let (sha256, text) = Catalog::finish_content(content, nested.is_some());
+
+                (nested, sha256, text)
+            };
+
+            let member_path = Catalog::member_path(container, &entry_path);
+
+            // A broken archive inside is indexed as a regular file
+            let is_archive = match nested {
+                Some(inner) => self.index_nested(&inner, ftar, &member_path, tree, parents_inos, depth),
+                None => false
+            };
+
             let indexed_file = IndexedFile {
-                full_path: full_path_str,
-                file_name: file_name_str,
-                mtime: header.mtime()
-                    .expect("Can't determine de mtime"),
-                size: header.size()
-                    .expect("Can't determine de size"),
-                is_file: header.entry_type().is_file(),
-                level_path: level,
-                ino: ino
+                full_path: member_path,
+                file_name: FileTar::path_to_string(full_path, false),
+                mtime: header.mtime().unwrap_or(0),
+                size: header.size().unwrap_or(0),
+                is_file: (is_file || is_hardlink) && !is_archive,
+                level_path: 0,
+                ino: 0,
+                offset: offset,
+                csize: 0,
+                is_archive: is_archive,
+                kind: Catalog::file_kind(header.entry_type()),
+                mode: header.mode().unwrap_or(0) & 0o7777,
+                uid: header.uid().unwrap_or(0),
+                gid: header.gid().unwrap_or(0),
+                user_name: header.username().unwrap_or(None)
+                    .map(|name| name.to_string()),
+                group_name: header.groupname().unwrap_or(None)
+                    .map(|name| name.to_string()),
+                link_name: entrie.link_name().unwrap_or(None)
+                    .map(|link| FileTar::path_to_string(&link, true)),
+                device_major: header.device_major().unwrap_or(None),
+                device_minor: header.device_minor().unwrap_or(None),
+                sha256: sha256
             };
 
-            let data = serde_json::to_string(&indexed_file)
-                .expect("Error on Serialize the file")
-                .to_string();
+            let mut indexed_file = indexed_file;
 
-            tree.set(FileTar::path_to_string(full_path, true).as_bytes(), data.as_bytes().to_vec())
-                .expect("Error on create index for a file");
+            // The target is always before of the hardlink on the tar
+            if is_hardlink {
+                let target = Catalog::link_path(container, &indexed_file)
+                    .and_then(|target| Catalog::read_file(tree, &target));
 
-            self.set_last_ino(ino);
-        }
+                if let Some(target) = target {
+                    indexed_file.size = target.size;
+                }
+            }
 
-        for (_parent, inos) in parents_inos {
-            let files = self.get_tree_inos(inos.0);
-            for file in inos.1 {
-                files.set(file.to_string().as_bytes(), file.to_string().as_bytes().to_vec())
-                    .expect("Error on set the index tree ino");
+            let full_path_str = indexed_file.full_path.clone();
+
+            self.index_file(ftar, tree, parents_inos, indexed_file, header.entry_type().is_dir());
+
+            if let Some(text) = text {
+                self.index_words(ftar, &full_path_str, &text);
             }
         }
 
-        self.db.flush()
-         .expect("Error on flush db");
+        return true;
+    }
 
-        info!("Indexing {}...OK", path.display());
+    // Index the entries of a zip file, from his central directory
+    fn index_zip(&self, path: &Path, ftar: &FileTar, container: &str, tree: &Tree, parents_inos: &mut Parents, depth: usize) -> bool {
 
-        return Some(ftar);
-    }
+        let entries = zipfile::entries(path);
 
-    // Return the sled Tree object for access the indexed content
-    // of a file
-    fn get_tree(&mut self, tar: &FileTar) -> Arc<Tree> {
+        if let Err(e) = entries {
+            error!("Can't read the zip file {}: {}. Skiping...", path.display(), e);
+            return false;
+        }
 
-        let files = self.db.open_tree(format!("tar::{}", tar.full_path.clone()))
-                .expect("Can't open the file tree");
+        // The parent folders are indexed before of his childs
+        let mut entries = entries.unwrap();
+        entries.sort_by(|a, b| a.name.cmp(&b.name));
 
-        return files;
-    }
+        for entry in entries {
 
-    // Return the sled Tree object for access the indexed content
-    // of a ino tree cache
-    fn get_tree_inos(&mut self, ino: u64) -> Arc<Tree> {
+            if !Catalog::is_valid_name(&entry.name) {
+                warn!("Invalid name of entry {} on {}. Skiping...", entry.name, path.display());
+                continue;
+            }
 
-        let internal_files = self.db.open_tree(format!("inotree::{}", ino))
-                .expect("Can't open the ino tree");
+            self.index_parents(ftar, container, &entry.name, entry.mtime, tree, parents_inos);
 
-        return internal_files;
-    }
+            let is_file = !entry.is_dir;
 
-    // Update the last used ino on files
-    fn set_last_ino(&mut self, ino: u64) {
+            let capture = self.capture_max(is_file, entry.size);
 
-        self.db.set("last_ino".to_string(), ino.to_string().as_bytes().to_vec())
-            .expect("Error on update the last ino");
-    }
+            let (nested, sha256, text) = if is_file && (depth < self.nested_depth || self.hash_contents || capture.is_some()) {
+                match zipfile::open_member(path, entry.offset, entry.csize) {
+                    Ok(member) => {
+                        let mut content = Capture::new(DigestReader::new(member.take(entry.size), self.hash_contents), capture);
 
-    // Return the last used ino on files
-    fn get_last_ino(&mut self) -> u64 {
+                        let nested = if depth < self.nested_depth && Catalog::is_archive_name(&entry.name) {
+                            self.nested_archive(&mut content)
+                        } else {
+                            None
+                        };
 
-        if let Ok(valop) = self.db.get("last_ino".to_string()) {
-            if let Some(val) = valop {
-                let inostr = str::from_utf8(&val)
-                    .expect("Error on get last ino from db");
+                        let (sha256, text) = Catalog::finish_content(content, nested.is_some());
 
-                if let Ok(ino) = inostr.parse::<u64>() {
-                    return ino;
+                        (nested, sha256, text)
+                    },
+                    Err(_) => (None, None, None)
                 }
+            } else {
+                (None, None, None)
+            };
+
+            let full_path = Path::new(&entry.name);
+
+            let member_path = Catalog::member_path(container, &entry.name);
+
+            // A broken archive inside is indexed as a regular file
+            let is_archive = match nested {
+                Some(inner) => self.index_nested(&inner, ftar, &member_path, tree, parents_inos, depth),
+                None => false
+            };
+
+            let indexed_file = IndexedFile {
+                full_path: member_path,
+                file_name: FileTar::path_to_string(full_path, false),
+                mtime: entry.mtime,
+                size: entry.size,
+                is_file: is_file && !is_archive,
+                level_path: 0,
+                ino: 0,
+                offset: entry.offset,
+                csize: entry.csize,
+                is_archive: is_archive,
+                kind: if entry.is_dir { FileKind::Directory } else { FileKind::Regular },
+                mode: entry.mode.unwrap_or(0) & 0o7777,
+                uid: 0,
+                gid: 0,
+                user_name: None,
+                group_name: None,
+                link_name: None,
+                device_major: None,
+                device_minor: None,
+                sha256: sha256
+            };
+
+            let full_path_str = indexed_file.full_path.clone();
+
+            self.index_file(ftar, tree, parents_inos, indexed_file, entry.is_dir);
+
+            if let Some(text) = text {
+                self.index_words(ftar, &full_path_str, &text);
             }
         }
 
-        return 20000;
+        return true;
     }
 
-    // Return the childs of the ino
-    pub fn get_files_inos(&mut self, ino: u64) -> Vec<u64> {
+    // Index the parent folders of the member that don't have his own
+    // entry on the archive, like on the zip files created without -D
+    // and the tar files created from a list of files
+    fn index_parents(&self, ftar: &FileTar, container: &str, name: &str, mtime: u64, tree: &Tree, parents_inos: &mut Parents) {
 
-        let sub_inos = self.get_tree_inos(ino);
+        let name = name.trim_end_matches('/');
 
-        let mut files: Vec<u64> = vec![];
+        for (end, _) in name.match_indices('/') {
 
-        for val in sub_inos.iter().values() {
+            let folder_name = name[..end].rsplit('/').next().unwrap_or("");
 
-            let uval = val.expect("Error on get the val of indexed ino");
+            if folder_name.is_empty() || folder_name == "." || folder_name == ".." {
+                continue;
+            }
 
-            let sub_ino_str = str::from_utf8(&uval)
-                .expect("Error on get string ut8 from indexed ino");
+            let full_path = Catalog::member_path(container, &name[..end + 1]);
+
+            if parents_inos.contains_key(&full_path) {
+                continue;
+            }
 
-            let sub_ino = sub_ino_str.parse::<u64>()
-                .expect("Error on parse the ino str");
+            let mut folder = IndexedFile::new_folder(full_path, 0);
+            folder.file_name = folder_name.to_string();
+            folder.mtime = mtime;
 
-            files.push(sub_ino);
+            self.index_file(ftar, tree, parents_inos, folder, true);
         }
+    }
 
-        files
+    // Max of bytes captured of the member for index his words, if
+    // he can be indexed
+    fn capture_max(&self, is_file: bool, size: u64) -> Option<usize> {
+        if is_file && size > 0 && size <= self.contents_max {
+            Some(size as usize)
+        } else {
+            None
+        }
     }
 
-    // Return the indexed files inside of the tar
-    pub fn get_catalog(&mut self, tar: &FileTar) -> Vec<IndexedFile> {
+    // Read the rest of the content and return his digest and, if is a
+    // text, his captured content. The archives inside are not texts
+    fn finish_content<R: Read>(mut content: Capture<DigestReader<R>>, is_archive: bool) -> (Option<String>, Option<Vec<u8>>) {
 
-        let tree = self.get_tree(tar);
-        let mut files: Vec<IndexedFile> = vec![];
+        if content.is_enabled() && !is_archive && copy(&mut content, &mut sink()).is_err() {
+            return (None, None);
+        }
 
-        for val in tree.iter().values() {
+        let (content, text) = content.into_parts();
 
-            let uval = val.expect("Error on get the val of indexed file");
+        let text = text.filter(|text| !is_archive && contents::is_text(text));
 
-            let file = str::from_utf8(&uval)
-                .expect("Error on get string ut8 from indexed file");
+        (content.finish(), text)
+    }
 
-            files.push(serde_json::from_str(&file)
-                .expect("Error on Deserialize the file"));
-        }
+    // Save the words of the text on the full-text index of the archive
+    fn index_words(&self, ftar: &FileTar, full_path: &str, text: &[u8]) {
 
-        return files;
+        let tree = self.get_tree_contents(ftar);
+
+        for word in contents::words(&String::from_utf8_lossy(text)) {
+            tree.set(Catalog::word_key(&word, full_path), vec![])
+                .expect("Error on create index for a word");
+        }
     }
 
-    // Return the list of indexed files(catalog's)
-    pub fn get_catalogs(&mut self) -> Vec<FileTar> {
-        let mut cats: Vec<FileTar> = vec![];
+    // If the member can be a archive, by his name
+    fn is_archive_name(name: &str) -> bool {
 
-        for ucat in self.db.tree_names() {
+        let name = name.to_lowercase();
 
-            let cat = str::from_utf8(&ucat)
-                .expect("Error on get string ut8 from catalog key");
+        ARCHIVE_EXTENSIONS.iter()
+            .any(|ext| name.ends_with(ext))
+    }
 
-            if !cat.starts_with("tar::") {
-                continue;
-            }
+    // Copy the content of a member to a temporary file, but only if he
+    // is a archive too. Compressed files, like the .log.gz, need have
+    // a tar inside
+    fn nested_archive<R: Read>(&self, content: &mut R) -> Option<PathBuf> {
 
-            let catn = cat.replacen("tar::", "", 1);
+        let mut head = vec![];
 
-            let path_buf = PathBuf::from(catn);
+        if content.by_ref().take(NESTED_HEAD).read_to_end(&mut head).is_err() {
+            return None;
+        }
 
-            cats.push(FileTar::from_path(path_buf.as_path()));
+        let is_archive = zipfile::is_zip_magic(&head) || Compression::from_magic(&head)
+            .and_then(|compression| compression.reader(&head[..]).ok())
+            .map_or(false, is_tar);
+
+        if !is_archive {
+            return None;
         }
 
-        cats
-    }
+        // Removed on the start of cache, if left by a crash
+        let temp = self.cache.temp_path();
 
-    // Return if the tar is already indexed
-    pub fn is_indexed(&mut self, tar: &FileTar) -> bool {
+        let file = File::create(&temp);
 
-        let tree = self.get_tree(tar);
+        if let Err(e) = file {
+            error!("Can't create the temporary file {}: {}. Skiping...", temp.display(), e);
+            return None;
+        }
 
-        for _val in tree.iter().values() {
-            return true;
+        let mut writer = BufWriter::new(file.unwrap());
+
+        if writer.write_all(&head).is_err() || copy(content, &mut writer).is_err() || writer.flush().is_err() {
+            error!("Error on write the temporary file {}. Skiping...", temp.display());
+            let _ = fs::remove_file(&temp);
+            return None;
         }
 
-        return false;
+        Some(temp)
     }
 
-    // Burn/remove the indexed content, if exists, of the file tar
-    pub fn burn_catalog(&mut self, tar: &FileTar) {
+    // Index a archive inside of the archive as a folder. His entries
+    // are named as <container>:<path>. When the archive is broken, his
+    // entries already indexed are removed and false is returned
+    fn index_nested(&self, inner: &Path, ftar: &FileTar, container: &str, tree: &Tree, parents_inos: &mut Parents, depth: usize) -> bool {
 
-        info!("Burning {}...", tar.full_path);
+        let ino = self.get_ino(ftar, container);
 
-        if !self.is_indexed(tar) {
-            warn!("Not indexed {}. Skiping...", tar.full_path);
-            return;
+        parents_inos.insert(format!("{}:", container), (ino, LinkedList::new()));
+
+        let indexed = self.index_archive(inner, ftar, container, tree, parents_inos, depth + 1);
+
+        if !indexed {
+            warn!("Can't index the nested archive {}:{}. Indexing as a file...", ftar.full_path, container);
+            self.unindex_nested(ftar, container, tree, parents_inos);
         }
 
-        self.db.drop_tree(tar.full_path.as_bytes())
-            .expect("Can't drop the file tree");
+        if let Err(e) = fs::remove_file(inner) {
+            warn!("Can't remove the temporary file {}: {}", inner.display(), e);
+        }
 
-        info!("Burning {}...OK", tar.full_path);
+        indexed
     }
 
-    // Extract a file from .tar file
-    pub fn extract_file(&self, ftar: &FileTar, ffile: &IndexedFile) -> Option<File> {
+    // Remove the entries of the archive inside of the archive
+    fn unindex_nested(&self, ftar: &FileTar, container: &str, tree: &Tree, parents_inos: &mut Parents) {
+
+        let prefix = format!("{}:", container);
+
+        parents_inos.retain(|parent, _| !parent.starts_with(&prefix));
+
+        let mut keys = vec![];
+
+        for val in tree.scan(prefix.as_bytes()) {
+
+            let (key, uval) = val.expect("Error on get the val of indexed file");
+
+            if !key.starts_with(prefix.as_bytes()) {
+                break;
+            }
+
+            let file: IndexedFile = serde_json::from_str(str::from_utf8(&uval)
+                    .expect("Error on get string ut8 from indexed file"))
+                .expect("Error on Deserialize the file");
+
+            if let Some(ref sha256) = file.sha256 {
+                self.get_tree_hashes().del(Catalog::hash_key(sha256, ftar, &file.full_path))
+                    .expect("Can't remove the hash of file");
+            }
 
-        let (is_cached, cache) = self.cached_file(ftar, ffile);
+            self.index.remove(ftar, &file);
 
-        if is_cached {
-            return Some(cache);
+            keys.push(key);
         }
 
-        let path = Path::new(&ftar.full_path);
-        let archive = File::open(path);
+        for key in keys {
+            tree.del(&key)
+                .expect("Can't remove the indexed file");
+        }
 
-        if let Err(e) = archive {
-            error!("Can't open the file {}: {}. Skiping...", path.display(), e);
-            return None;
+        // The words are indexed by word and path
+        let contents = self.get_tree_contents(ftar);
+
+        for val in contents.iter().keys() {
+
+            let key = val.expect("Error on get the key of indexed word");
+
+            let is_nested = key.iter().position(|&b| b == 0)
+                .map_or(false, |pos| key[pos + 1..].starts_with(prefix.as_bytes()));
+
+            if is_nested {
+                contents.del(&key)
+                    .expect("Can't remove the indexed word");
+            }
         }
 
-        let buffer_archive = BufReader::new(archive.unwrap());
+        let seek = format!("seek::{}:{}", ftar.full_path, container);
 
-        let decoder = GzDecoder::new(buffer_archive);
-        let buffer_decoder = BufReader::new(decoder);
+        for name in self.db.tree_names() {
+            if name.starts_with(seek.as_bytes()) {
+                self.db.drop_tree(&name)
+                    .expect("Can't drop the seek tree");
+            }
+        }
+    }
 
-        let mut tar = Archive::new(buffer_decoder);
+    // Type of the tar entry on the index
+    fn file_kind(entry_type: EntryType) -> FileKind {
+        match entry_type {
+            EntryType::Regular | EntryType::Continuous | EntryType::GNUSparse => FileKind::Regular,
+            EntryType::Directory => FileKind::Directory,
+            EntryType::Symlink => FileKind::Symlink,
+            EntryType::Link => FileKind::Hardlink,
+            EntryType::Char => FileKind::CharDevice,
+            EntryType::Block => FileKind::BlockDevice,
+            EntryType::Fifo => FileKind::Fifo,
+            _ => FileKind::Unknown
+        }
+    }
 
-        let entries = tar.entries()
-            .expect("Error on get the entries of tar file");
+    // Path of the tar entry on the index, without the ./ of the
+    // archives created from the current folder, like `tar -C / .`
+    fn entry_path(path: &Path) -> String {
 
-        for entrie in entries {
+        let full_path = FileTar::path_to_string(path, true);
 
-            let file = entrie
-                .expect("Erro on get the entrie file");
+        let mut entry_path = full_path.as_str();
 
-            let header = file.header().clone();
+        while entry_path.starts_with("./") {
+            entry_path = entry_path[2..].trim_start_matches('/');
+        }
 
-            let full_path = &header.path()
-                .expect("Can't get the full path");
+        if entry_path == "." {
+            return String::new();
+        }
 
-            if FileTar::path_to_string(full_path, true) == ffile.full_path {
+        entry_path.to_string()
+    }
 
-                // Make the cache for use in the next requests
-                copy(&mut BufReader::new(file), &mut BufWriter::new(cache))
-                    .expect("Error on make the cache");
+    // The names out of the archive, like the ../x, and without a file
+    // name can't be indexed
+    fn is_valid_name(name: &str) -> bool {
+        !name.split('/').any(|part| part == "..") && Path::new(name).file_name().is_some()
+    }
 
-                // We get the content from cache
-                return self.extract_file(ftar, ffile);
-            }
+    // Path of a member, with the path of his container archive
+    fn member_path(container: &str, path: &str) -> String {
+
+        if container.is_empty() {
+            return path.to_string();
         }
 
-        return None;
+        format!("{}:{}", container, path)
     }
 
-    // Return the cache of indexed file, if exists
-    fn cached_file(&self, ftar: &FileTar, ffile: &IndexedFile) -> (bool, File) {
+    // Save the file on the tree of the archive and register him
+    // as child of his parent folder
+    fn index_file(&self, ftar: &FileTar, tree: &Tree, parents_inos: &mut Parents, mut indexed_file: IndexedFile, is_dir: bool) -> u64 {
+
+        let ino = self.get_ino(ftar, &indexed_file.full_path);
+
+        let full_path_str = indexed_file.full_path.clone();
 
-        let cached_name = format!("{}/{}_{}", self.cache_extract, ftar.file_name, ffile.full_path.replace("/", "_"));
+        let mut level = full_path_str.matches("/").count() + full_path_str.matches(":").count();
 
-        let path = Path::new(&cached_name);
+        if !is_dir {
+            level = level + 1;
+        }
+
+        let parent_n = full_path_str.rfind(indexed_file.file_name.as_str())
+            .expect("Error on get the pos of file name");
+        let parent = &full_path_str[..parent_n];
+
+        // The folder can be already indexed by his childs, before of
+        // his own entry on the tar
+        let is_new = !is_dir || !parents_inos.contains_key(&full_path_str);
+
+        if is_dir && is_new {
+            parents_inos.insert(full_path_str.clone(), (ino, LinkedList::new()));
+        }
+
+        if let Some(parent_list) = parents_inos.get_mut(parent).filter(|_| is_new) {
+            parent_list.1.push_back((indexed_file.file_name.clone(), ino));
+        }
+
+        indexed_file.level_path = level;
+        indexed_file.ino = ino;
 
-        if path.exists() {
-            let file = File::open(path)
-                .expect("Cant open the cache file");
+        let data = serde_json::to_string(&indexed_file)
+            .expect("Error on Serialize the file")
+            .to_string();
 
-            return (true, file);
+        tree.set(full_path_str.as_bytes(), data.as_bytes().to_vec())
+            .expect("Error on create index for a file");
+
+        self.index.add(ftar, &indexed_file);
+
+        if let Some(ref sha256) = indexed_file.sha256 {
+            if indexed_file.is_file {
+                self.get_tree_hashes().set(Catalog::hash_key(sha256, ftar, &full_path_str), indexed_file.size.to_be_bytes().to_vec())
+                    .expect("Error on create index for the hash of file");
+            }
         }
 
-        let file = File::create(path)
-            .expect("Cant create the cache file");
+        return ino;
+    }
+
+    // Return the sled Tree object for access the indexed content
+    // of a file
+    fn get_tree(&self, tar: &FileTar) -> Arc<Tree> {
+
+        let files = self.db.open_tree(format!("tar::{}", tar.full_path.clone()))
+                .expect("Can't open the file tree");
+
+        return files;
+    }
+
+    // Return the sled Tree object for access the checkpoints
+    // of the decompression of a file, or of a archive inside him
+    fn get_tree_seek(&self, tar: &FileTar, container: &str) -> Arc<Tree> {
+
+        let name = if container.is_empty() {
+            format!("seek::{}", tar.full_path)
+        } else {
+            format!("seek::{}:{}", tar.full_path, container)
+        };
+
+        let points = self.db.open_tree(name)
+                .expect("Can't open the seek tree");
+
+        return points;
+    }
+
+    // Return the sled Tree object for access the indexed content
+    // of a ino tree cache
+    fn get_tree_inos(&self, ino: u64) -> Arc<Tree> {
+
+        let internal_files = self.db.open_tree(format!("inotree::{}", ino))
+                .expect("Can't open the ino tree");
+
+        return internal_files;
+    }
+
+    // Return the sled Tree object for access the files of the archive
+    // by the words of his content
+    fn get_tree_contents(&self, tar: &FileTar) -> Arc<Tree> {
+
+        let words = self.db.open_tree(format!("contents::{}", tar.full_path))
+                .expect("Can't open the contents tree");
+
+        return words;
+    }
+
+    // Key of a file on the contents tree
+    fn word_key(word: &str, full_path: &str) -> Vec<u8> {
+        format!("{}\0{}", word, full_path).into_bytes()
+    }
+
+    // Return the sled Tree object for access the files by the digest
+    // of his content
+    fn get_tree_hashes(&self) -> Arc<Tree> {
+
+        let hashes = self.db.open_tree("hashes")
+                .expect("Can't open the hashes tree");
+
+        return hashes;
+    }
+
+    // Key of a file on the hashes tree. The files with the same
+    // content stay together
+    fn hash_key(sha256: &str, tar: &FileTar, full_path: &str) -> Vec<u8> {
+        format!("{}\0{}\0{}", sha256, tar.full_path, full_path).into_bytes()
+    }
+
+    // Return the lock of the indexed content of the tar
+    fn get_lock(&self, tar: &FileTar) -> Arc<RwLock<()>> {
+
+        let mut locks = self.locks.lock()
+            .expect("Error on lock the list of locks");
+
+        locks.entry(tar.full_path.clone())
+            .or_insert_with(|| Arc::new(RwLock::new(())))
+            .clone()
+    }
+
+    // Return the lock of the extraction of the file
+    fn get_extract_lock(&self, cache_key: &str) -> Arc<Mutex<()>> {
+
+        let mut extracting = self.extracting.lock()
+            .expect("Error on lock the list of extractions");
+
+        extracting.entry(cache_key.to_string())
+            .or_insert_with(|| Arc::new(Mutex::new(())))
+            .clone()
+    }
+
+    // Forget the lock of the extraction, if nobody else is waiting him
+    fn release_extract_lock(&self, cache_key: &str, lock: Arc<Mutex<()>>) {
+
+        let mut extracting = self.extracting.lock()
+            .expect("Error on lock the list of extractions");
+
+        drop(lock);
+
+        let unused = extracting.get(cache_key)
+            .map_or(false, |lock| Arc::strong_count(lock) == 1);
+
+        if unused {
+            extracting.remove(cache_key);
+        }
+    }
+
+    // Reserve a new ino for a file
+    fn next_ino(&self) -> u64 {
+
+        let _reserving = self.ino_lock.lock()
+            .expect("Error on lock the last ino");
+
+        let ino = self.get_last_ino() + 1;
+
+        self.set_last_ino(ino);
+
+        ino
+    }
+
+    // Return the sled Tree object for access the childs of
+    // a ino, ordered by his position on the listing
+    fn get_tree_inos_list(&self, ino: u64) -> Arc<Tree> {
+
+        let list = self.db.open_tree(format!("inolist::{}", ino))
+                .expect("Can't open the ino list tree");
+
+        return list;
+    }
+
+    // Value of a child on the list of childs: ino + name
+    fn child_to_bytes(name: &str, ino: u64) -> Vec<u8> {
+
+        let mut data = ino.to_be_bytes().to_vec();
+        data.extend_from_slice(name.as_bytes());
+
+        data
+    }
+
+    // Update the last used ino on files
+    fn set_last_ino(&self, ino: u64) {
+
+        self.db.set("last_ino".to_string(), ino.to_string().as_bytes().to_vec())
+            .expect("Error on update the last ino");
+    }
+
+    // Return the last used ino on files
+    fn get_last_ino(&self) -> u64 {
+
+        if let Ok(valop) = self.db.get("last_ino".to_string()) {
+            if let Some(val) = valop {
+                let inostr = str::from_utf8(&val)
+                    .expect("Error on get last ino from db");
+
+                if let Ok(ino) = inostr.parse::<u64>() {
+                    return ino;
+                }
+            }
+        }
+
+        // The ino 1 is the root of mount
+        return 1;
+    }
+
+    // Return the ino of a file of the tar, the empty path is the
+    // root of the tar. The ino is the same after a new indexing
+    fn get_ino(&self, tar: &FileTar, full_path: &str) -> u64 {
+
+        let key = format!("{}\0{}", tar.full_path, full_path);
+
+        let inodes = self.db.open_tree("inodes")
+            .expect("Can't open the inodes tree");
+
+        if let Ok(Some(val)) = inodes.get(key.as_bytes()) {
+            return Catalog::bytes_to_ino(&val);
+        }
+
+        let ino = self.next_ino();
+
+        let inodes_rev = self.db.open_tree("inodes_rev")
+            .expect("Can't open the inodes reverse tree");
+
+        inodes.set(key.as_bytes(), ino.to_be_bytes().to_vec())
+            .expect("Error on save the ino of file");
+
+        inodes_rev.set(ino.to_be_bytes().to_vec(), key.as_bytes().to_vec())
+            .expect("Error on save the file of ino");
+
+        ino
+    }
+
+    // Return the ino of the root of tar, if indexed
+    pub fn get_tar_ino(&self, tar: &FileTar) -> Option<u64> {
+
+        let inodes = self.db.open_tree("inodes")
+            .expect("Can't open the inodes tree");
+
+        if let Ok(Some(val)) = inodes.get(format!("{}\0", tar.full_path).as_bytes()) {
+            return Some(Catalog::bytes_to_ino(&val));
+        }
+
+        None
+    }
+
+    // Return the tar and the full path of file with the ino. The empty
+    // path is the root of the tar
+    pub fn get_inode(&self, ino: u64) -> Option<(FileTar, String)> {
+
+        let inodes_rev = self.db.open_tree("inodes_rev")
+            .expect("Can't open the inodes reverse tree");
+
+        if let Ok(Some(val)) = inodes_rev.get(ino.to_be_bytes().to_vec()) {
+
+            let key = str::from_utf8(&val)
+                .expect("Error on get string ut8 from inode");
+
+            let mut slices = key.splitn(2, '\0');
+
+            let tar = slices.next()
+                .expect("Tar of inode not found");
+            let full_path = slices.next()
+                .unwrap_or("");
+
+            return Some((FileTar::from_path(Path::new(tar)), full_path.to_string()));
+        }
+
+        None
+    }
+
+    fn bytes_to_ino(val: &[u8]) -> u64 {
+
+        let mut ino = [0u8; 8];
+        ino.copy_from_slice(&val[..8]);
+
+        u64::from_be_bytes(ino)
+    }
+
+    // Return until max childs of the ino, starting from the position.
+    // Each child is returned with his position, name and ino
+    pub fn get_childs_page(&self, ino: u64, position: u64, max: usize) -> Vec<(u64, String, u64)> {
+
+        let list = self.get_tree_inos_list(ino);
+
+        let mut files: Vec<(u64, String, u64)> = vec![];
+
+        for val in list.scan(position.to_be_bytes().to_vec()).take(max) {
+
+            let (key, uval) = val.expect("Error on get the val of listed ino");
+
+            let name = str::from_utf8(&uval[8..])
+                .expect("Error on get string ut8 from listed ino name");
+
+            files.push((Catalog::bytes_to_ino(&key), name.to_string(), Catalog::bytes_to_ino(&uval)));
+        }
+
+        files
+    }
+
+    // Return the ino of a child, by his name
+    pub fn get_child_ino(&self, ino: u64, name: &str) -> Option<u64> {
+
+        let sub_inos = self.get_tree_inos(ino);
+
+        if let Ok(Some(val)) = sub_inos.get(name.as_bytes()) {
+
+            let sub_ino_str = str::from_utf8(&val)
+                .expect("Error on get string ut8 from indexed ino");
+
+            return sub_ino_str.parse::<u64>().ok();
+        }
+
+        None
+    }
+
+    // Return the tar and the indexed file of the ino. The root of
+    // the tar is returned as a folder
+    pub fn get_inode_file(&self, ino: u64) -> Option<(FileTar, IndexedFile)> {
+
+        let (tar, full_path) = self.get_inode(ino)?;
+
+        if full_path.is_empty() {
+            let root = IndexedFile::new_folder(tar.file_name.clone(), ino);
+
+            return Some((tar, root));
+        }
+
+        let file = self.get_file(&tar, &full_path)?;
+
+        Some((tar, file))
+    }
+
+    // Return the file or folder of the archive. The folders can be
+    // without the '/' on the end and the empty path is the root
+    pub fn get_entry(&self, tar: &FileTar, full_path: &str) -> Option<IndexedFile> {
+
+        if full_path.is_empty() {
+
+            let (_tar, mut root) = self.get_inode_file(self.get_tar_ino(tar)?)?;
+
+            // The root has the mtime of the archive
+            root.mtime = fs::metadata(&tar.full_path)
+                .and_then(|meta| meta.modified())
+                .ok()
+                .and_then(|mtime| mtime.duration_since(UNIX_EPOCH).ok())
+                .map(|mtime| mtime.as_secs())
+                .unwrap_or(0);
+
+            return Some(root);
+        }
+
+        self.get_file(tar, full_path)
+            .or_else(|| {
+                if full_path.ends_with('/') {
+                    None
+                } else {
+                    self.get_file(tar, &format!("{}/", full_path))
+                }
+            })
+    }
+
+    // Return the files and folders inside of the folder
+    pub fn get_childs(&self, ino: u64) -> Vec<(FileTar, IndexedFile)> {
+        self.get_childs_page(ino, 0, usize::max_value()).into_iter()
+            .filter_map(|(_position, _name, child)| self.get_inode_file(child))
+            .collect()
+    }
+
+    // Return the indexed files inside of the tar
+    pub fn get_catalog(&self, tar: &FileTar) -> Vec<IndexedFile> {
+
+        let lock = self.get_lock(tar);
+        let _reading = lock.read()
+            .expect("Error on lock the tar for reading");
+
+        let tree = self.get_tree(tar);
+        let mut files: Vec<IndexedFile> = vec![];
+
+        for val in tree.iter().values() {
+
+            let uval = val.expect("Error on get the val of indexed file");
+
+            let file = str::from_utf8(&uval)
+                .expect("Error on get string ut8 from indexed file");
+
+            files.push(serde_json::from_str(&file)
+                .expect("Error on Deserialize the file"));
+        }
+
+        return files;
+    }
+
+    // Return a indexed file of the tar by his full path
+    pub fn get_file(&self, tar: &FileTar, full_path: &str) -> Option<IndexedFile> {
+
+        let lock = self.get_lock(tar);
+        let _reading = lock.read()
+            .expect("Error on lock the tar for reading");
+
+        self.find_file(tar, full_path)
+    }
+
+    // Same of get_file, but without wait for the lock of tar
+    fn find_file(&self, tar: &FileTar, full_path: &str) -> Option<IndexedFile> {
+        Catalog::read_file(&self.get_tree(tar), full_path)
+    }
+
+    // Read the indexed file from the tree of the tar
+    fn read_file(tree: &Tree, full_path: &str) -> Option<IndexedFile> {
+
+        if let Ok(Some(val)) = tree.get(full_path.as_bytes()) {
+
+            let file = str::from_utf8(&val)
+                .expect("Error on get string ut8 from indexed file");
+
+            return Some(serde_json::from_str(&file)
+                .expect("Error on Deserialize the file"));
+        }
+
+        return None;
+    }
+
+    // Return the file pointed by the symlinks and hardlinks. Others
+    // files are returned as they are
+    pub fn resolve_link(&self, tar: &FileTar, file: IndexedFile) -> Option<IndexedFile> {
+
+        let lock = self.get_lock(tar);
+        let _reading = lock.read()
+            .expect("Error on lock the tar for reading");
+
+        self.follow_link(tar, file)
+    }
+
+    // Same of resolve_link, but without wait for the lock of tar
+    fn follow_link(&self, tar: &FileTar, mut file: IndexedFile) -> Option<IndexedFile> {
+
+        for _ in 0..MAX_LINKS {
+
+            if file.kind != FileKind::Symlink && file.kind != FileKind::Hardlink {
+                return Some(file);
+            }
+
+            let container = self.container_of(tar, &file)
+                .map_or("", |(n, _container)| &file.full_path[..n]);
+
+            let target = Catalog::link_path(container, &file)?;
+
+            // The folders are indexed with the '/' on the end
+            file = self.find_file(tar, &target)
+                .or_else(|| self.find_file(tar, &format!("{}/", target)))?;
+        }
+
+        warn!("Too many levels of links on {}:{}", tar.full_path, file.full_path);
+
+        None
+    }
+
+    // Full path of the target of a link, inside of his container. The
+    // symlinks are relative to his folder and the hardlinks to the root
+    // of his archive
+    fn link_path(container: &str, file: &IndexedFile) -> Option<String> {
+
+        let link_name = file.link_name.as_ref()?;
+
+        let member = if container.is_empty() {
+            file.full_path.as_str()
+        } else {
+            &file.full_path[container.len() + 1..]
+        };
+
+        let mut base = vec![];
+
+        if file.kind == FileKind::Symlink && !link_name.starts_with('/') {
+            base.extend(member.split('/'));
+            base.pop(); // name of the link
+        }
+
+        let mut parts: Vec<&str> = vec![];
+
+        for part in base.into_iter().chain(link_name.split('/')) {
+            match part {
+                "" | "." => {},
+                ".." => {
+                    parts.pop();
+                },
+                _ => parts.push(part)
+            }
+        }
+
+        Some(Catalog::member_path(container, &parts.join("/")))
+    }
+
+    // Return the tar and the full path of the files with the content
+    // of the digest
+    pub fn get_by_hash(&self, sha256: &str) -> Vec<(FileTar, String)> {
+
+        let prefix = format!("{}\0", sha256);
+
+        let mut files = vec![];
+
+        for val in self.get_tree_hashes().scan(prefix.as_bytes()) {
+
+            let (key, _size) = val.expect("Error on get the val of indexed hash");
+
+            if !key.starts_with(prefix.as_bytes()) {
+                break;
+            }
+
+            let key = str::from_utf8(&key[prefix.len()..])
+                .expect("Error on get string ut8 from indexed hash");
+
+            let mut slices = key.splitn(2, '\0');
+
+            if let (Some(tar), Some(full_path)) = (slices.next(), slices.next()) {
+                files.push((FileTar::from_path(Path::new(tar)), full_path.to_string()));
+            }
+        }
+
+        files
+    }
+
+    // Return the groups of files with the same content, as the digest,
+    // the size and the files of each group
+    pub fn get_duplicates(&self) -> Vec<(String, u64, Vec<(FileTar, String)>)> {
+
+        let mut groups = vec![];
+        let mut group: Option<(String, u64, Vec<(FileTar, String)>)> = None;
+
+        for val in self.get_tree_hashes().iter() {
+
+            let (key, size) = val.expect("Error on get the val of indexed hash");
+
+            let key = str::from_utf8(&key)
+                .expect("Error on get string ut8 from indexed hash");
+
+            let mut slices = key.splitn(3, '\0');
+
+            let (sha256, tar, full_path) = match (slices.next(), slices.next(), slices.next()) {
+                (Some(sha256), Some(tar), Some(full_path)) => (sha256, tar, full_path),
+                _ => continue
+            };
+
+            let same = match group {
+                Some(ref group) => group.0 == sha256,
+                None => false
+            };
+
+            if !same {
+                if let Some(group) = group.take() {
+                    if group.2.len() > 1 {
+                        groups.push(group);
+                    }
+                }
+
+                group = Some((sha256.to_string(), Catalog::bytes_to_ino(&size), vec![]));
+            }
+
+            if let Some(ref mut group) = group {
+                group.2.push((FileTar::from_path(Path::new(tar)), full_path.to_string()));
+            }
+        }
+
+        if let Some(group) = group {
+            if group.2.len() > 1 {
+                groups.push(group);
+            }
+        }
+
+        groups
+    }
+
+    // Extract the content of the digest from the archive cheapest
+    // to extract
+    pub fn extract_by_hash(&self, sha256: &str) -> Option<File> {
+
+        let mut cheapest: Option<(u64, FileTar, IndexedFile)> = None;
+
+        for (tar, full_path) in self.get_by_hash(sha256) {
+
+            let lock = self.get_lock(&tar);
+            let _reading = lock.read()
+                .expect("Error on lock the tar for reading");
+
+            if let Some(file) = self.find_file(&tar, &full_path) {
+
+                let cost = self.extract_cost(&tar, &file);
+
+                let is_cheaper = match cheapest {
+                    Some((min, _, _)) => cost < min,
+                    None => true
+                };
+
+                if is_cheaper {
+                    cheapest = Some((cost, tar.clone(), file));
+                }
+            }
+        }
+
+        let (_cost, tar, file) = cheapest?;
+
+        self.extract_file(&tar, &file)
+    }
+
+    // Search the text, ignoring the case, on the lines of the files
+    // with the words indexed. The files are found by the words of the
+    // text and confirmed by his content
+    pub fn grep<F>(&self, text: &str, mut found: F) where F: FnMut(&FileTar, &IndexedFile, usize, &str) -> bool {
+
+        let needle = text.to_lowercase();
+
+        let word = contents::words(text).into_iter()
+            .max_by_key(|word| word.chars().count());
+
+        if word.is_none() {
+            return;
+        }
+
+        let word = word.unwrap();
+        let tokens = contents::tokens(text);
+
+        for tar in self.get_catalogs() {
+
+            // Only the lookup of the files waits the lock of the tar,
+            // the lines are sent without him because the client can
+            // be slow
+            let files: Vec<IndexedFile> = {
+                let lock = self.get_lock(&tar);
+                let _reading = lock.read()
+                    .expect("Error on lock the tar for reading");
+
+                self.grep_paths(&tar, &tokens, &word).into_iter()
+                    .filter_map(|full_path| self.find_file(&tar, &full_path))
+                    .collect()
+            };
+
+            for file in files {
+
+                let extracted = match self.extract_file(&tar, &file) {
+                    Some(extracted) => extracted,
+                    None => {
+                        warn!("Can't extract {}:{} for grep. Skiping...", tar.full_path, file.full_path);
+                        continue;
+                    }
+                };
+
+                let mut reader = BufReader::new(extracted);
+                let mut line = vec![];
+                let mut number = 0;
+
+                loop {
+                    line.clear();
+
+                    match reader.read_until(b'\n', &mut line) {
+                        Ok(0) | Err(_) => break,
+                        Ok(_) => number = number + 1
+                    }
+
+                    let line = String::from_utf8_lossy(&line);
+                    let line = line.trim_end_matches(|c| c == '\n' || c == '\r');
+
+                    if line.to_lowercase().contains(needle.as_str()) && !found(&tar, &file, number, line) {
+                        return;
+                    }
+                }
+            }
+        }
+    }
+
+    // Paths of the files that can have the words of the text. Only the
+    // first and the last words can be incomplete, like "nection refu",
+    // so the words between them are found by his exact value and the
+    // last by his start. The text of only one word can be on the middle
+    // of a word, like "ostname" of "hostname", so all the indexed words
+    // are readed for find the ones with him inside
+    fn grep_paths(&self, tar: &FileTar, tokens: &[String], word: &str) -> BTreeSet<String> {
+
+        let tree = self.get_tree_contents(tar);
+
+        let mut found: Option<BTreeSet<String>> = None;
+
+        for (i, token) in tokens.iter().enumerate().skip(1) {
+
+            if !contents::is_word(token) {
+                continue;
+            }
+
+            let mut prefix = token.as_bytes().to_vec();
+
+            // Complete word
+            if i < tokens.len() - 1 {
+                prefix.push(0);
+            }
+
+            let paths = Catalog::word_paths(&tree, &prefix);
+
+            found = Some(match found {
+                Some(found) => found.intersection(&paths).cloned().collect(),
+                None => paths
+            });
+        }
+
+        match found {
+            Some(paths) => paths,
+            None => Catalog::scan_words(&tree, word)
+        }
+    }
+
+    // Paths of the indexed words starting with the prefix
+    fn word_paths(tree: &Tree, prefix: &[u8]) -> BTreeSet<String> {
+
+        let mut paths = BTreeSet::new();
+
+        for val in tree.scan(prefix) {
+
+            let (key, _val) = val.expect("Error on get the key of indexed word");
+
+            if !key.starts_with(prefix) {
+                break;
+            }
+
+            if let Some(end) = key.iter().position(|&b| b == 0) {
+                paths.insert(String::from_utf8_lossy(&key[end + 1..]).to_string());
+            }
+        }
+
+        paths
+    }
+
+    // Paths of the indexed words that have the word inside. The paths
+    // of the others words are skipped, so each distinct word is readed
+    // once
+    fn scan_words(tree: &Tree, word: &str) -> BTreeSet<String> {
+
+        let mut paths = BTreeSet::new();
+        let mut next = vec![];
+
+        loop {
+            let key = match tree.scan(&next).next() {
+                Some(Ok((key, _val))) => key,
+                _ => break
+            };
+
+            let end = key.iter().position(|&b| b == 0)
+                .unwrap_or(key.len());
+
+            next = if String::from_utf8_lossy(&key[..end]).contains(word) {
+                if end < key.len() {
+                    paths.insert(String::from_utf8_lossy(&key[end + 1..]).to_string());
+                }
+
+                // The next path of the word
+                let mut next = key.to_vec();
+                next.push(0);
+                next
+            } else {
+                // The first key after the paths of the word
+                let mut next = key[..end].to_vec();
+                next.push(1);
+                next
+            };
+        }
+
+        paths
+    }
+
+    // Return the list of indexed files(catalog's)
+    pub fn get_catalogs(&self) -> Vec<FileTar> {
+        let mut cats: Vec<FileTar> = vec![];
+
+        for ucat in self.db.tree_names() {
+
+            let cat = str::from_utf8(&ucat)
+                .expect("Error on get string ut8 from catalog key");
+
+            if !cat.starts_with("tar::") {
+                continue;
+            }
+
+            let catn = cat.replacen("tar::", "", 1);
+
+            let path_buf = PathBuf::from(catn);
+
+            cats.push(FileTar::from_path(path_buf.as_path()));
+        }
+
+        cats
+    }
+
+    // Return the indexed file(catalog) by his name, from the childs of
+    // the root of mount, without read the list of all catalogs
+    pub fn get_catalog_by_name(&self, name: &str) -> Option<FileTar> {
+
+        let ino = self.get_child_ino(1, name)?;
+
+        self.get_inode(ino)
+            .map(|(tar, _full_path)| tar)
+    }
+
+    // Return if the tar is already indexed
+    pub fn is_indexed(&self, tar: &FileTar) -> bool {
+
+        let tree = self.get_tree(tar);
+
+        for _val in tree.iter().values() {
+            return true;
+        }
+
+        return false;
+    }
+
+    // Read again the archive and return the entries whose content
+    // don't match with the digest of the indexing. The members of the
+    // archives inside of the archive are verified by his container
+    pub fn verify(&self, tar: &FileTar) -> Option<Vec<(String, String)>> {
+
+        info!("Verifying {}...", tar.full_path);
+
+        let lock = self.get_lock(tar);
+        let _reading = lock.read()
+            .expect("Error on lock the tar for reading");
+
+        if !self.is_indexed(tar) {
+            warn!("Not indexed {}. Skiping...", tar.full_path);
+            return None;
+        }
+
+        let mut containers = vec![];
+        let mut expected: HashMap<String, String> = HashMap::new();
+
+        for val in self.get_tree(tar).iter().values() {
+
+            let uval = val.expect("Error on get the val of indexed file");
+
+            let file: IndexedFile = serde_json::from_str(str::from_utf8(&uval)
+                    .expect("Error on get string ut8 from indexed file"))
+                .expect("Error on Deserialize the file");
+
+            if file.is_archive {
+                containers.push(format!("{}:", file.full_path));
+            }
+
+            if let Some(sha256) = file.sha256 {
+                expected.insert(file.full_path, sha256);
+            }
+        }
+
+        expected.retain(|full_path, _| !containers.iter().any(|container| full_path.starts_with(container.as_str())));
+
+        let path = Path::new(&tar.full_path);
+
+        let mut failed: Vec<(String, String)> = vec![];
+        let mut broken = false;
+
+        if zipfile::is_zip(path) {
+
+            // Without the central directory the entries are lost
+            let entries = zipfile::entries(path).unwrap_or_else(|e| {
+                error!("Can't read the zip file {}: {}", path.display(), e);
+                broken = true;
+                vec![]
+            });
+
+            for entry in entries {
+
+                if let Some(sha256) = expected.remove(&entry.name) {
+
+                    let actual = zipfile::open_member(path, entry.offset, entry.csize).ok()
+                        .and_then(|member| digest::sha256(member.take(entry.size)));
+
+                    match actual {
+                        Some(ref actual) if *actual == sha256 => {},
+                        Some(_) => failed.push((entry.name, "mismatch".to_string())),
+                        None => failed.push((entry.name, "unreadable".to_string()))
+                    }
+                }
+            }
+        } else {
+
+            let decoder = Compression::detect(path)
+                .ok_or(io::Error::new(io::ErrorKind::InvalidData, "Unknown compression"))
+                .and_then(|compression| compression.decoder(path));
+
+            if let Err(e) = decoder {
+                error!("Can't open the file {}: {}. Skiping...", path.display(), e);
+                return None;
+            }
+
+            let mut archive = Archive::new(BufReader::new(decoder.unwrap()));
+
+            match archive.entries() {
+                Ok(entries) => {
+                    for entrie in entries {
+
+                        // After a error the rest of archive is lost
+                        let file = match entrie {
+                            Ok(file) => file,
+                            Err(_) => {
+                                broken = true;
+                                break;
+                            }
+                        };
+
+                        let full_path = match file.header().path() {
+                            Ok(full_path) => Catalog::entry_path(&full_path),
+                            Err(_) => continue
+                        };
+
+                        if let Some(sha256) = expected.remove(&full_path) {
+                            match digest::sha256(file) {
+                                Some(ref actual) if *actual == sha256 => {},
+                                Some(_) => failed.push((full_path, "mismatch".to_string())),
+                                None => {
+                                    failed.push((full_path, "unreadable".to_string()));
+                                    broken = true;
+                                    break;
+                                }
+                            }
+                        }
+                    }
+                },
+                Err(_) => broken = true
+            }
+        }
+
+        // Entries not founded on the archive
+        let problem = if broken { "unreadable" } else { "missing" };
+
+        for (full_path, _sha256) in expected {
+            failed.push((full_path, problem.to_string()));
+        }
+
+        failed.sort();
+
+        info!("Verifying {}...OK", tar.full_path);
+
+        Some(failed)
+    }
+
+    // Burn/remove the indexed content, if exists, of the file tar
+    pub fn burn_catalog(&self, tar: &FileTar) {
+
+        info!("Burning {}...", tar.full_path);
+
+        let lock = self.get_lock(tar);
+        let _writing = lock.write()
+            .expect("Error on lock the tar for burning");
+
+        if !self.is_indexed(tar) {
+            warn!("Not indexed {}. Skiping...", tar.full_path);
+            return;
+        }
+
+        // Childs of the folders. The inos are keeped, so after a new
+        // indexing the files have the same inos
+        if let Some(ino) = self.get_tar_ino(tar) {
+            self.db.drop_tree(format!("inotree::{}", ino).as_bytes())
+                .expect("Can't drop the ino tree");
+            self.db.drop_tree(format!("inolist::{}", ino).as_bytes())
+                .expect("Can't drop the ino list tree");
+
+            self.get_tree_inos(1).del(tar.file_name.as_bytes())
+                .expect("Can't remove the tar from root");
+
+            let root_list = self.get_tree_inos_list(1);
+
+            for val in root_list.iter() {
+                let (key, uval) = val.expect("Error on get the val of listed ino");
+
+                if Catalog::bytes_to_ino(&uval) == ino {
+                    root_list.del(&key)
+                        .expect("Can't remove the tar from root list");
+                }
+            }
+        }
+
+        for file in self.get_tree(tar).iter().values() {
+
+            let uval = file.expect("Error on get the val of indexed file");
+
+            let file: IndexedFile = serde_json::from_str(str::from_utf8(&uval)
+                    .expect("Error on get string ut8 from indexed file"))
+                .expect("Error on Deserialize the file");
+
+            if let Some(ref sha256) = file.sha256 {
+                self.get_tree_hashes().del(Catalog::hash_key(sha256, tar, &file.full_path))
+                    .expect("Can't remove the hash of file");
+            }
+
+            self.index.remove(tar, &file);
+
+            if !file.is_file {
+                self.db.drop_tree(format!("inotree::{}", file.ino).as_bytes())
+                    .expect("Can't drop the ino tree");
+                self.db.drop_tree(format!("inolist::{}", file.ino).as_bytes())
+                    .expect("Can't drop the ino list tree");
+            }
+        }
+
+        self.db.drop_tree(format!("tar::{}", tar.full_path).as_bytes())
+            .expect("Can't drop the file tree");
+
+        self.db.drop_tree(format!("contents::{}", tar.full_path).as_bytes())
+            .expect("Can't drop the contents tree");
+
+        self.index.remove_archive(tar);
+
+        // The archive was changed or removed, so his extracted files
+        // are not valid anymore
+        self.cache.remove_prefix(&Catalog::cache_prefix(tar));
+
+        // Checkpoints of the file and of the archives inside him
+        let seek_nested = format!("seek::{}:", tar.full_path);
+
+        for name in self.db.tree_names() {
+            if name == format!("seek::{}", tar.full_path).as_bytes() || name.starts_with(seek_nested.as_bytes()) {
+                self.db.drop_tree(&name)
+                    .expect("Can't drop the seek tree");
+            }
+        }
+
+        info!("Burning {}...OK", tar.full_path);
+    }
+
+    // Extract a file from .tar or .zip file
+    pub fn extract_file(&self, ftar: &FileTar, ffile: &IndexedFile) -> Option<File> {
+
+        let lock = self.get_lock(ftar);
+        let _reading = lock.read()
+            .expect("Error on lock the tar for reading");
+
+        self.extract(ftar, ffile)
+    }
+
+    // Same of extract_file, but without wait for the lock of tar
+    fn extract(&self, ftar: &FileTar, ffile: &IndexedFile) -> Option<File> {
+
+        // The content of hardlinks is on his target
+        if ffile.kind == FileKind::Hardlink {
+
+            let target = self.follow_link(ftar, ffile.clone());
+
+            if target.is_none() {
+                error!("Can't find the target of hardlink {}:{}. Skiping...", ftar.full_path, ffile.full_path);
+                return None;
+            }
+
+            return self.extract(ftar, &target.unwrap());
+        }
+
+        let cache_key = self.cache_key(ftar, ffile);
+
+        // Only one request extracts the file, the others wait for him
+        // and use his cached file
+        let lock = self.get_extract_lock(&cache_key);
+
+        let extracted = {
+            let _extracting = lock.lock()
+                .expect("Error on lock the file for extracting");
+
+            self.cache.get(&cache_key, ffile.size)
+                .or_else(|| self.extract_content(ftar, ffile, &cache_key))
+        };
+
+        self.release_extract_lock(&cache_key, lock);
+
+        extracted
+    }
+
+    // Extract the file and save him on the cache
+    fn extract_content(&self, ftar: &FileTar, ffile: &IndexedFile, cache_key: &str) -> Option<File> {
+
+        // Member of a archive inside of the archive, so we
+        // need the content of his container first
+        let (path, container, member) = match self.container_of(ftar, ffile) {
+            Some((n, fcontainer)) => {
+
+                if self.extract(ftar, &fcontainer).is_none() {
+                    error!("Can't extract the nested archive {}:{}. Skiping...", ftar.full_path, fcontainer.full_path);
+                    return None;
+                }
+
+                (self.cache.path(&self.cache_key(ftar, &fcontainer)), &ffile.full_path[..n], &ffile.full_path[n + 1..])
+            },
+            None => (PathBuf::from(&ftar.full_path), "", ffile.full_path.as_str())
+        };
+
+        let path = path.as_path();
+
+        // The zip members are extracted directly from his local header
+        if zipfile::is_zip(path) {
+
+            let reader = zipfile::open_member(path, ffile.offset, ffile.csize);
+
+            if let Err(e) = reader {
+                error!("Can't read the member of zip {}: {}. Skiping...", path.display(), e);
+                return None;
+            }
+
+            let mut content = reader.unwrap().take(ffile.size);
+
+            // Make the cache for use in the next requests
+            return self.cache.put(cache_key, ffile.size, &mut content);
+        }
+
+        let compression = Compression::detect(path);
+        if compression.is_none() {
+            error!("Can't detect the compression of {}. Skiping...", path.display());
+            return None;
+        }
+
+        let compression = compression.unwrap();
+
+        // With the offset of content we start from the nearest point
+        if ffile.offset > 0 {
+
+            let points = self.get_tree_seek(ftar, container);
+
+            let reader = compression.decoder_at(path, &points, ffile.offset);
+
+            if let Err(e) = reader {
+                error!("Can't seek the file {}: {}. Skiping...", path.display(), e);
+                return None;
+            }
+
+            let mut content = reader.unwrap().take(ffile.size);
+
+            // Make the cache for use in the next requests
+            return self.cache.put(cache_key, ffile.size, &mut content);
+        }
+
+        let decoder = compression.decoder(path);
+
+        if let Err(e) = decoder {
+            error!("Can't open the file {}: {}. Skiping...", path.display(), e);
+            return None;
+        }
+
+        let buffer_decoder = BufReader::new(decoder.unwrap());
+
+        let mut tar = Archive::new(buffer_decoder);
+
+        let entries = tar.entries()
+            .expect("Error on get the entries of tar file");
+
+        for entrie in entries {
+
+            let file = entrie
+                .expect("Erro on get the entrie file");
+
+            let header = file.header().clone();
+
+            let full_path = &header.path()
+                .expect("Can't get the full path");
+
+            if Catalog::entry_path(full_path) == member {
+
+                // Make the cache for use in the next requests
+                return self.cache.put(cache_key, ffile.size, &mut BufReader::new(file));
+            }
+        }
+
+        return None;
+    }
+
+    // Part of the content of a file, from the start until the length
+    // or the end of file. The cached files are seeked, and the members
+    // of plain and gzip tar files are decompressed from the nearest
+    // point, so only the others need extract all content before
+    pub fn extract_range(&self, ftar: &FileTar, ffile: &IndexedFile, start: u64, length: u64) -> Option<Box<dyn Read>> {
+
+        let lock = self.get_lock(ftar);
+        let _reading = lock.read()
+            .expect("Error on lock the tar for reading");
+
+        // The content of hardlinks is on his target
+        let ffile = if ffile.kind == FileKind::Hardlink {
+            match self.follow_link(ftar, ffile.clone()) {
+                Some(target) => target,
+                None => {
+                    error!("Can't find the target of hardlink {}:{}. Skiping...", ftar.full_path, ffile.full_path);
+                    return None;
+                }
+            }
+        } else {
+            ffile.clone()
+        };
+
+        if start > ffile.size {
+            error!("Invalid start {} for {}:{} with {} bytes", start, ftar.full_path, ffile.full_path, ffile.size);
+            return None;
+        }
+
+        let length = length.min(ffile.size - start);
+
+        let path = Path::new(&ftar.full_path);
+
+        let compression = Compression::detect(path);
+
+        let seekable = !self.cache.contains(&self.cache_key(ftar, &ffile))
+            && ffile.offset > 0
+            && self.container_of(ftar, &ffile).is_none()
+            && !zipfile::is_zip(path)
+            && (compression == Some(Compression::Gzip) || compression == Some(Compression::Plain));
+
+        if seekable {
+
+            let points = self.get_tree_seek(ftar, "");
+
+            return match compression.unwrap().decoder_at(path, &points, ffile.offset + start) {
+                Ok(reader) => Some(Box::new(reader.take(length))),
+                Err(e) => {
+                    error!("Can't seek the file {}: {}. Skiping...", path.display(), e);
+                    None
+                }
+            };
+        }
+
+        let mut extracted = self.extract(ftar, &ffile)?;
+
+        if let Err(e) = extracted.seek(SeekFrom::Start(start)) {
+            error!("Can't seek the extracted file {}:{}: {}", ftar.full_path, ffile.full_path, e);
+            return None;
+        }
+
+        Some(Box::new(extracted.take(length)))
+    }
+
+    // Return the archive inside of the archive that contains the file,
+    // with the position of his ':' on the full path of file. The names
+    // can have ':' too, like the files of Maildir, so the container is
+    // the nearest archive
+    fn container_of(&self, ftar: &FileTar, ffile: &IndexedFile) -> Option<(usize, IndexedFile)> {
+
+        let tree = self.get_tree(ftar);
+
+        ffile.full_path.match_indices(':').rev()
+            .filter_map(|(n, _)| {
+                Catalog::read_file(&tree, &ffile.full_path[..n])
+                    .filter(|container| container.is_archive)
+                    .map(|container| (n, container))
+            })
+            .next()
+    }
+
+    // Estimate of the bytes readed for extract the file, used for
+    // choose between the copies of the same content
+    fn extract_cost(&self, ftar: &FileTar, ffile: &IndexedFile) -> u64 {
+
+        if self.cache.contains(&self.cache_key(ftar, ffile)) {
+            return 0;
+        }
+
+        let (path, container, cost) = match self.container_of(ftar, ffile) {
+            Some((n, fcontainer)) => {
+                (self.cache.path(&self.cache_key(ftar, &fcontainer)), &ffile.full_path[..n], self.extract_cost(ftar, &fcontainer))
+            },
+            None => (PathBuf::from(&ftar.full_path), "", 0)
+        };
+
+        let path = path.as_path();
+
+        // Container not extracted yet, so we don't know his format
+        if !path.exists() {
+            return cost + ffile.offset + ffile.size;
+        }
+
+        if zipfile::is_zip(path) {
+            return cost + ffile.csize;
+        }
+
+        match Compression::detect(path) {
+            Some(Compression::Plain) => cost + ffile.size,
+            Some(Compression::Gzip) => {
+                // Decompressed from the nearest checkpoint
+                let start = match self.get_tree_seek(ftar, container).get_lt((ffile.offset + 1).to_be_bytes().to_vec()) {
+                    Ok(Some((key, _val))) => Catalog::bytes_to_ino(&key),
+                    _ => 0
+                };
+
+                cost + ffile.offset - start + ffile.size
+            },
+            _ => cost + ffile.offset + ffile.size
+        }
+    }
+
+    // Name of the file on the cache. The files with digest are
+    // cached by his content, so the copies on others archives
+    // use the same cache. The others are cached by the version of
+    // his archive, so a changed archive don't use the old cache
+    fn cache_key(&self, ftar: &FileTar, ffile: &IndexedFile) -> String {
+
+        if let Some(ref sha256) = ffile.sha256 {
+            return format!("sha256_{}", sha256);
+        }
+
+        let version = match fs::metadata(&ftar.full_path) {
+            Ok(meta) => {
+                let mtime = meta.modified().ok()
+                    .and_then(|mtime| mtime.duration_since(UNIX_EPOCH).ok())
+                    .map(|mtime| mtime.as_secs())
+                    .unwrap_or(0);
+
+                format!("{}\0{}", mtime, meta.len())
+            },
+            Err(_) => String::new()
+        };
+
+        let entry = digest::sha256(format!("{}\0{}", version, ffile.full_path).as_bytes())
+            .expect("Error on make the cache key");
+
+        format!("{}{}", Catalog::cache_prefix(ftar), entry)
+    }
+
+    // Start of the cache names of the files without digest of the
+    // archive
+    fn cache_prefix(ftar: &FileTar) -> String {
+
+        let archive = digest::sha256(ftar.full_path.as_bytes())
+            .expect("Error on make the cache prefix");
+
+        format!("tar_{}_", &archive[..16])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use std::env;
+    use std::fs;
+    use std::io::{Cursor, Read, Write};
+    use std::process;
+
+    use sled::Db;
+    use tar::{Builder, EntryType, Header};
+    use zip::ZipWriter;
+    use zip::write::FileOptions;
+
+    use super::Catalog;
+    use super::super::cache::Cache;
+
+    // Append the entry with his name as is, because the tar crate
+    // removes the ./ of the names
+    fn append(builder: &mut Builder<Vec<u8>>, name: &str, entry_type: EntryType, link_name: Option<&str>, content: &[u8]) {
+
+        let mut header = Header::new_gnu();
+
+        header.as_old_mut().name[..name.len()].copy_from_slice(name.as_bytes());
+        header.set_entry_type(entry_type);
+        header.set_mode(0o644);
+        header.set_size(content.len() as u64);
+
+        if let Some(link_name) = link_name {
+            header.set_link_name(link_name).unwrap();
+        }
+
+        header.set_cksum();
+
+        builder.append(&header, content).unwrap();
+    }
+
+    fn read_all<R: Read>(mut content: R) -> Vec<u8> {
+
+        let mut data = vec![];
+        content.read_to_end(&mut data).unwrap();
+
+        data
+    }
+
+    // Like the backups created by `tar -C / .`
+    #[test]
+    fn links_of_archive_from_current_folder() {
+
+        let dir = env::temp_dir().join(format!("blitze_links_{}", process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+
+        let mut builder = Builder::new(vec![]);
+
+        append(&mut builder, "./", EntryType::Directory, None, b"");
+        append(&mut builder, "./usr/", EntryType::Directory, None, b"");
+        append(&mut builder, "./usr/bin/", EntryType::Directory, None, b"");
+        append(&mut builder, "./usr/bin/x", EntryType::Regular, None, b"content\n");
+        append(&mut builder, "./usr/bin/hard", EntryType::Link, Some("./usr/bin/x"), b"");
+        append(&mut builder, "./usr/bin/rel", EntryType::Symlink, Some("x"), b"");
+        append(&mut builder, "./usr/bin/abs", EntryType::Symlink, Some("/usr/bin/x"), b"");
+
+        let path = dir.join("rootfs.tar");
+        fs::write(&path, builder.into_inner().unwrap()).unwrap();
+
+        let db = Db::start_default(dir.join("db")).unwrap();
+        let cache = Cache::new(db.clone(), dir.join("cache").to_str().unwrap().to_string(), 1048576);
+        let catalog = Catalog::new(db, cache, 0, false, 0);
+
+        let ftar = catalog.catalog_file(&path)
+            .expect("The archive was not indexed");
+
+        // The hardlink has the size and the content of his target
+        let hard = catalog.get_file(&ftar, "usr/bin/hard")
+            .expect("The hardlink was not indexed");
+
+        assert_eq!(hard.size, 8);
+        assert_eq!(read_all(catalog.extract_file(&ftar, &hard).unwrap()), b"content\n");
+
+        for link in &["usr/bin/hard", "usr/bin/rel", "usr/bin/abs"] {
+
+            let file = catalog.get_file(&ftar, link)
+                .expect("The link was not indexed");
+
+            let target = catalog.resolve_link(&ftar, file)
+                .expect("The target of link was not found");
+
+            assert_eq!(target.full_path, "usr/bin/x");
+            assert_eq!(read_all(catalog.extract_file(&ftar, &target).unwrap()), b"content\n");
+        }
+
+        drop(catalog);
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    // Names with ':' are not archives inside of the archive
+    #[test]
+    fn links_with_colon_on_names() {
+
+        let dir = env::temp_dir().join(format!("blitze_colon_{}", process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+
+        let mut builder = Builder::new(vec![]);
+
+        // Like the messages of Maildir
+        append(&mut builder, "cur/a:2,S", EntryType::Regular, None, b"message\n");
+        append(&mut builder, "cur/b:2,S", EntryType::Link, Some("cur/a:2,S"), b"");
+        append(&mut builder, "logs/y", EntryType::Regular, None, b"uptime\n");
+        append(&mut builder, "logs/12:00/up", EntryType::Symlink, Some("../y"), b"");
+
+        let path = dir.join("mail.tar");
+        fs::write(&path, builder.into_inner().unwrap()).unwrap();
+
+        let db = Db::start_default(dir.join("db")).unwrap();
+        let cache = Cache::new(db.clone(), dir.join("cache").to_str().unwrap().to_string(), 1048576);
+        let catalog = Catalog::new(db, cache, 0, false, 0);
+
+        let ftar = catalog.catalog_file(&path)
+            .expect("The archive was not indexed");
+
+        let hard = catalog.get_file(&ftar, "cur/b:2,S")
+            .expect("The hardlink was not indexed");
+
+        assert_eq!(hard.size, 8);
+
+        let target = catalog.resolve_link(&ftar, hard.clone())
+            .expect("The target of hardlink was not found");
+
+        assert_eq!(target.full_path, "cur/a:2,S");
+        assert_eq!(read_all(catalog.extract_file(&ftar, &hard).unwrap()), b"message\n");
+
+        let symlink = catalog.get_file(&ftar, "logs/12:00/up")
+            .expect("The symlink was not indexed");
+
+        let target = catalog.resolve_link(&ftar, symlink)
+            .expect("The target of symlink was not found");
+
+        assert_eq!(target.full_path, "logs/y");
+        assert_eq!(read_all(catalog.extract_file(&ftar, &target).unwrap()), b"uptime\n");
+
+        drop(catalog);
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    // The entries out of the archive are skiped, without a panic
+    #[test]
+    fn zip_with_parent_on_names() {
+
+        let dir = env::temp_dir().join(format!("blitze_parent_{}", process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+
+        let mut zip = ZipWriter::new(Cursor::new(vec![]));
+
+        for name in &["docs/a.txt", "../evil.txt", "docs/..", "docs/../b.txt"] {
+            zip.start_file(*name, FileOptions::default()).unwrap();
+            zip.write_all(b"content\n").unwrap();
+        }
+
+        let path = dir.join("parent.zip");
+        fs::write(&path, zip.finish().unwrap().into_inner()).unwrap();
+
+        let db = Db::start_default(dir.join("db")).unwrap();
+        let cache = Cache::new(db.clone(), dir.join("cache").to_str().unwrap().to_string(), 1048576);
+        let catalog = Catalog::new(db, cache, 0, false, 0);
+
+        let ftar = catalog.catalog_file(&path)
+            .expect("The archive was not indexed");
+
+        let file = catalog.get_file(&ftar, "docs/a.txt")
+            .expect("The valid entry was not indexed");
+
+        assert_eq!(read_all(catalog.extract_file(&ftar, &file).unwrap()), b"content\n");
+
+        for name in &["../evil.txt", "evil.txt", "docs/..", "docs/../b.txt", "b.txt"] {
+            assert!(catalog.get_file(&ftar, name).is_none());
+        }
 
-        (false, file)
+        drop(catalog);
+        let _ = fs::remove_dir_all(&dir);
     }
 }
@@ -0,0 +1,137 @@
+///
+/// Blitz Explorer
+///
+/// Content of the text files, captured while indexing, for the
+/// full-text index of the words
+///
+/// Copyright 2019 Luis Fernando Batels <luisfbatels@gmail.com>
+///
+
+use std::io::{self, Read};
+use std::str;
+use std::collections::HashSet;
+
+// Bytes readed for guess if the content is a text
+const SNIFF_SIZE: usize = 8192;
+
+// Limits of the indexed words, in chars
+const MIN_WORD: usize = 2;
+const MAX_WORD: usize = 64;
+
+// Keep a copy of the content readed, until a max of bytes
+pub struct Capture<R> {
+    inner: R,
+    buf: Option<Vec<u8>>, // none when disabled or bigger than the max
+    max: usize
+}
+
+impl<R: Read> Capture<R> {
+
+    pub fn new(inner: R, max: Option<usize>) -> Capture<R> {
+        Capture {
+            inner: inner,
+            buf: max.map(|_| vec![]),
+            max: max.unwrap_or(0)
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.buf.is_some()
+    }
+
+    // The reader and the content captured
+    pub fn into_parts(self) -> (R, Option<Vec<u8>>) {
+        (self.inner, self.buf)
+    }
+}
+
+impl<R: Read> Read for Capture<R> {
+
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+
+        let n = self.inner.read(buf)?;
+
+        let over = match self.buf {
+            Some(ref mut captured) => {
+                captured.extend_from_slice(&buf[..n]);
+                captured.len() > self.max
+            },
+            None => false
+        };
+
+        if over {
+            self.buf = None;
+        }
+
+        Ok(n)
+    }
+}
+
+// Guess if the content is a text by his first bytes, like the
+// sniffing of mime types: the binaries have nul bytes or too
+// many control chars
+pub fn is_text(content: &[u8]) -> bool {
+
+    if content.is_empty() {
+        return false;
+    }
+
+    let head = &content[..content.len().min(SNIFF_SIZE)];
+
+    if head.contains(&0) {
+        return false;
+    }
+
+    // A utf-8 char can be cutted on the end of the head
+    match str::from_utf8(head) {
+        Ok(_) => return true,
+        Err(e) if e.error_len().is_none() => return true,
+        Err(_) => {}
+    }
+
+    // Texts on other encodings, like latin-1
+    let controls = head.iter()
+        .filter(|&&b| (b < 0x20 && !b"\t\n\r\x0c\x1b".contains(&b)) || b == 0x7f)
+        .count();
+
+    controls * 100 <= head.len()
+}
+
+// The distinct words of the text, in lowercase
+pub fn words(text: &str) -> Vec<String> {
+
+    let mut words = vec![];
+    let mut seen = HashSet::new();
+
+    for word in text.split(|c: char| !c.is_alphanumeric()) {
+
+        if !is_word(word) {
+            continue;
+        }
+
+        let word = word.to_lowercase();
+
+        if seen.insert(word.clone()) {
+            words.push(word);
+        }
+    }
+
+    words
+}
+
+// All the words of the text, in order and lowercase, also the ones
+// that are not indexed
+pub fn tokens(text: &str) -> Vec<String> {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(|word| word.to_lowercase())
+        .collect()
+}
+
+// If the word has the size of the indexed words
+pub fn is_word(word: &str) -> bool {
+
+    let len = word.chars().count();
+
+    len >= MIN_WORD && len <= MAX_WORD
+}
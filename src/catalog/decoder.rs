@@ -0,0 +1,159 @@
+///
+/// Blitz Explorer
+///
+/// Decompressors of the tar files. The compression is detected
+/// by the magic bytes of the file, not by his extension
+///
+/// Copyright 2019 Luis Fernando Batels <luisfbatels@gmail.com>
+///
+
+use std::fs::File;
+use std::path::Path;
+use std::io::{self, Read, BufReader, Seek, SeekFrom, copy, sink};
+
+use flate2::read::MultiGzDecoder;
+use bzip2::read::MultiBzDecoder;
+use xz2::read::XzDecoder;
+use zstd::stream::read::Decoder as ZstdDecoder;
+use sled::Tree;
+
+use super::gzindex::GzSeekReader;
+
+// Bytes needed for detect all the formats, the tar magic is
+// on the offset 257
+pub const MAGIC_SIZE: usize = 265;
+
+// Size of the header of a tar entry
+const HEADER_SIZE: u64 = 512;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Compression {
+    Gzip,
+    Bzip2,
+    Xz,
+    Zstd,
+    Plain // uncompressed tar
+}
+
+impl Compression {
+
+    // Detect the compression of the file by his magic bytes
+    pub fn detect(path: &Path) -> Option<Compression> {
+
+        let file = File::open(path);
+
+        if file.is_err() {
+            return None;
+        }
+
+        let mut magic = vec![];
+
+        if file.unwrap().take(MAGIC_SIZE as u64).read_to_end(&mut magic).is_err() {
+            return None;
+        }
+
+        Compression::from_magic(&magic)
+    }
+
+    // Detect the compression of the file, but only if his content is
+    // a tar. Compressed files like the .log.gz are not archives
+    pub fn detect_tar(path: &Path) -> Option<Compression> {
+
+        let compression = Compression::detect(path)?;
+
+        if compression.decoder(path).map(is_tar).unwrap_or(false) {
+            Some(compression)
+        } else {
+            None
+        }
+    }
+
+    // Detect the compression by the first bytes of the content
+    pub fn from_magic(magic: &[u8]) -> Option<Compression> {
+
+        if magic.starts_with(&[0x1f, 0x8b]) {
+            return Some(Compression::Gzip);
+        }
+
+        if magic.starts_with(b"BZh") {
+            return Some(Compression::Bzip2);
+        }
+
+        if magic.starts_with(&[0xfd, b'7', b'z', b'X', b'Z', 0x00]) {
+            return Some(Compression::Xz);
+        }
+
+        if magic.starts_with(&[0x28, 0xb5, 0x2f, 0xfd]) {
+            return Some(Compression::Zstd);
+        }
+
+        // Uncompressed tar
+        if is_tar_header(magic) {
+            return Some(Compression::Plain);
+        }
+
+        None
+    }
+
+    // Open the decompressed content of the file, from the start
+    pub fn decoder(&self, path: &Path) -> io::Result<Box<dyn Read>> {
+        self.reader(File::open(path)?)
+    }
+
+    // Decompress the content of the reader
+    pub fn reader<'a, R: Read + 'a>(&self, input: R) -> io::Result<Box<dyn Read + 'a>> {
+
+        let input = BufReader::new(input);
+
+        let decoder: Box<dyn Read + 'a> = match *self {
+            Compression::Gzip => Box::new(MultiGzDecoder::new(input)),
+            Compression::Bzip2 => Box::new(MultiBzDecoder::new(input)),
+            Compression::Xz => Box::new(XzDecoder::new_multi_decoder(input)),
+            Compression::Zstd => Box::new(ZstdDecoder::new(input)?),
+            Compression::Plain => Box::new(input)
+        };
+
+        Ok(decoder)
+    }
+
+    // Open the decompressed content of the file positioned on the
+    // offset. The gzip files use the checkpoints of decompression
+    pub fn decoder_at(&self, path: &Path, points: &Tree, offset: u64) -> io::Result<Box<dyn Read>> {
+
+        match *self {
+            Compression::Gzip => {
+                Ok(Box::new(GzSeekReader::open(path, points, offset)?))
+            },
+            Compression::Plain => {
+                let mut archive = File::open(path)?;
+                archive.seek(SeekFrom::Start(offset))?;
+
+                Ok(Box::new(BufReader::new(archive)))
+            },
+            _ => {
+                let mut decoder = self.decoder(path)?;
+
+                copy(&mut Read::by_ref(&mut decoder).take(offset), &mut sink())?;
+
+                Ok(decoder)
+            }
+        }
+    }
+}
+
+// Return if the decompressed content starts with a tar header
+pub fn is_tar<R: Read>(content: R) -> bool {
+
+    let mut header = vec![];
+
+    if content.take(HEADER_SIZE).read_to_end(&mut header).is_err() {
+        return false;
+    }
+
+    is_tar_header(&header)
+}
+
+// POSIX ustar and GNU tar headers have the magic on the offset 257
+fn is_tar_header(header: &[u8]) -> bool {
+    header.len() >= 262 && &header[257..262] == b"ustar"
+}
@@ -0,0 +1,59 @@
+///
+/// Blitz Explorer
+///
+/// Digest of the content of the indexed files, calculated while
+/// the content is readed
+///
+/// Copyright 2019 Luis Fernando Batels <luisfbatels@gmail.com>
+///
+
+use std::io::{self, Read, copy, sink};
+
+use sha2::{Sha256, Digest};
+
+pub struct DigestReader<R> {
+    inner: R,
+    hasher: Option<Sha256> // none when the digest is disabled
+}
+
+impl<R: Read> DigestReader<R> {
+
+    pub fn new(inner: R, enabled: bool) -> DigestReader<R> {
+        DigestReader {
+            inner: inner,
+            hasher: if enabled { Some(Sha256::new()) } else { None }
+        }
+    }
+
+    // Read the rest of the content and return the SHA-256 of all
+    // content, in hex
+    pub fn finish(mut self) -> Option<String> {
+
+        self.hasher.as_ref()?;
+
+        if copy(&mut self, &mut sink()).is_err() {
+            return None;
+        }
+
+        self.hasher.map(|hasher| format!("{:x}", hasher.finalize()))
+    }
+}
+
+impl<R: Read> Read for DigestReader<R> {
+
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+
+        let n = self.inner.read(buf)?;
+
+        if let Some(ref mut hasher) = self.hasher {
+            hasher.update(&buf[..n]);
+        }
+
+        Ok(n)
+    }
+}
+
+// SHA-256 of all content of the reader, in hex
+pub fn sha256<R: Read>(reader: R) -> Option<String> {
+    DigestReader::new(reader, true).finish()
+}
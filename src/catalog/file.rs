@@ -9,8 +9,9 @@
 use std::path::Path;
 
 use serde::{Serialize, Deserialize};
+use serde_json::Value;
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct File {
     pub size: u64,
     pub mtime: u64,
@@ -18,7 +19,123 @@ pub struct File {
     pub full_path: String,
     pub is_file: bool,
     pub level_path: usize,
-    pub ino: u64
+    pub ino: u64,
+    #[serde(default)]
+    pub offset: u64, // offset of the content in the uncompressed tar, or of the zip local header
+    #[serde(default)]
+    pub csize: u64, // compressed size, only for the zip members
+    #[serde(default)]
+    pub is_archive: bool, // archive inside of the archive, indexed as a folder
+    #[serde(default)]
+    pub kind: FileKind,
+    #[serde(default)]
+    pub mode: u32, // permission bits
+    #[serde(default)]
+    pub uid: u64,
+    #[serde(default)]
+    pub gid: u64,
+    #[serde(default)]
+    pub user_name: Option<String>,
+    #[serde(default)]
+    pub group_name: Option<String>,
+    #[serde(default)]
+    pub link_name: Option<String>, // target of symlinks and hardlinks
+    #[serde(default)]
+    pub device_major: Option<u32>,
+    #[serde(default)]
+    pub device_minor: Option<u32>,
+    #[serde(default)]
+    pub sha256: Option<String> // digest of the content, only if enabled on the indexing
+}
+
+// Type of the entry inside of the archive
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum FileKind {
+    Regular,
+    Directory,
+    Symlink,
+    Hardlink,
+    CharDevice,
+    BlockDevice,
+    Fifo,
+    Unknown // indexed by old versions
+}
+
+impl Default for FileKind {
+    fn default() -> FileKind {
+        FileKind::Unknown
+    }
+}
+
+impl FileKind {
+
+    // Name of the type, for the users
+    pub fn name(&self) -> &str {
+        match *self {
+            FileKind::Regular => "regular",
+            FileKind::Directory => "directory",
+            FileKind::Symlink => "symlink",
+            FileKind::Hardlink => "hardlink",
+            FileKind::CharDevice => "char device",
+            FileKind::BlockDevice => "block device",
+            FileKind::Fifo => "fifo",
+            FileKind::Unknown => "unknown"
+        }
+    }
+}
+
+impl File {
+
+    // Create a folder that is not a entry of a tar, like
+    // the root of the tar
+    pub fn new_folder(name: String, ino: u64) -> File {
+        File {
+            full_path: name.clone(),
+            file_name: name,
+            mtime: 0,
+            size: 0,
+            is_file: false,
+            level_path: 0,
+            ino: ino,
+            offset: 0,
+            csize: 0,
+            is_archive: false,
+            kind: FileKind::Directory,
+            mode: 0o555,
+            uid: 0,
+            gid: 0,
+            user_name: None,
+            group_name: None,
+            link_name: None,
+            device_major: None,
+            device_minor: None,
+            sha256: None
+        }
+    }
+
+    // Metadata of the file for the clients, on the listings of the
+    // http api and of the tcp protocol
+    pub fn to_json(&self, tar: &FileTar) -> Value {
+        json!({
+            "archive": tar.file_name,
+            "path": self.full_path,
+            "name": self.file_name,
+            "type": self.kind.name(),
+            "is_file": self.is_file,
+            "is_archive": self.is_archive,
+            "size": self.size,
+            "mtime": self.mtime,
+            "mode": self.mode,
+            "uid": self.uid,
+            "gid": self.gid,
+            "user": self.user_name,
+            "group": self.group_name,
+            "link": self.link_name,
+            "device_major": self.device_major,
+            "device_minor": self.device_minor,
+            "sha256": self.sha256
+        })
+    }
 }
 
 pub struct FileTar {
@@ -0,0 +1,624 @@
+///
+/// Blitz Explorer
+///
+/// Checkpoints(seek points) of the gzip streams. With this we can
+/// start the decompression near of the indexed file instead of the
+/// start of the tar.gz file. Based on the zran.c example of zlib
+///
+/// Copyright 2019 Luis Fernando Batels <luisfbatels@gmail.com>
+///
+
+use std::fs::File;
+use std::path::Path;
+use std::io::{self, Read, Write, Seek, SeekFrom, ErrorKind, copy, sink};
+use std::os::raw::{c_int, c_uint, c_void};
+use std::sync::Arc;
+use std::mem;
+use std::ptr;
+
+use flate2::Compression;
+use flate2::read::DeflateDecoder;
+use flate2::write::DeflateEncoder;
+use libz_sys as zlib;
+use sled::Tree;
+
+// Distance, in uncompressed bytes, between the checkpoints
+pub const SPAN: u64 = 8 * 1048576;
+
+// Size of the deflate window, needed for restart the decompression
+const WINSIZE: usize = 32768;
+
+// Size of the buffer of compressed input
+const CHUNK: usize = 65536;
+
+// Window bits of the zlib for auto detect gzip/zlib header
+const GZIP_WBITS: c_int = 15 + 32;
+
+// Window bits of the zlib for raw deflate data
+const RAW_WBITS: c_int = -15;
+
+// Size of the gzip member trailer(crc32 + isize)
+const GZIP_TRAILER: u64 = 8;
+
+extern "C" fn zalloc(_opaque: *mut c_void, items: c_uint, size: c_uint) -> *mut c_void {
+    unsafe { libc::calloc(items as usize, size as usize) }
+}
+
+extern "C" fn zfree(_opaque: *mut c_void, address: *mut c_void) {
+    unsafe { libc::free(address) }
+}
+
+// Point where the decompression can be restarted
+pub struct Checkpoint {
+    pub output: u64, // offset in the uncompressed stream
+    pub input: u64, // offset in the compressed file
+    pub bits: u8, // bits of the byte before the input offset used by the next block
+    pub window: Vec<u8> // last 32K of uncompressed data before the checkpoint
+}
+
+impl Checkpoint {
+
+    // Serialize the checkpoint for store on the index. The key is
+    // the output offset, so it is not included here
+    pub fn to_bytes(&self) -> Vec<u8> {
+
+        let mut data = self.input.to_be_bytes().to_vec();
+        data.push(self.bits);
+
+        let mut encoder = DeflateEncoder::new(data, Compression::fast());
+
+        encoder.write_all(&self.window)
+            .expect("Error on compress the checkpoint window");
+
+        encoder.finish()
+            .expect("Error on compress the checkpoint window")
+    }
+
+    // Rebuild the checkpoint from his index key and value
+    pub fn from_bytes(key: &[u8], data: &[u8]) -> Option<Checkpoint> {
+
+        if key.len() != 8 || data.len() < 9 {
+            return None;
+        }
+
+        let mut output = [0u8; 8];
+        output.copy_from_slice(key);
+
+        let mut input = [0u8; 8];
+        input.copy_from_slice(&data[..8]);
+
+        let mut window = vec![];
+        if DeflateDecoder::new(&data[9..]).read_to_end(&mut window).is_err() {
+            return None;
+        }
+
+        Some(Checkpoint {
+            output: u64::from_be_bytes(output),
+            input: u64::from_be_bytes(input),
+            bits: data[8],
+            window: window
+        })
+    }
+}
+
+// Safe wrapper of the zlib inflate stream
+struct Inflater {
+    strm: Box<zlib::z_stream> // zlib keeps a pointer to the stream, so it can't move
+}
+
+impl Inflater {
+
+    fn new(window_bits: c_int) -> io::Result<Inflater> {
+
+        let mut strm = Box::new(zlib::z_stream {
+            next_in: ptr::null_mut(),
+            avail_in: 0,
+            total_in: 0,
+            next_out: ptr::null_mut(),
+            avail_out: 0,
+            total_out: 0,
+            msg: ptr::null_mut(),
+            state: ptr::null_mut(),
+            zalloc: zalloc,
+            zfree: zfree,
+            opaque: ptr::null_mut(),
+            data_type: 0,
+            adler: 0,
+            reserved: 0
+        });
+
+        let ret = unsafe {
+            zlib::inflateInit2_(&mut *strm, window_bits, zlib::zlibVersion(), mem::size_of::<zlib::z_stream>() as c_int)
+        };
+
+        if ret != zlib::Z_OK {
+            return Err(io::Error::new(ErrorKind::Other, "Error on start the inflate stream"));
+        }
+
+        Ok(Inflater {
+            strm: strm
+        })
+    }
+
+    // Restart the stream, used between the gzip members
+    fn reset(&mut self, window_bits: c_int) -> io::Result<()> {
+
+        if unsafe { zlib::inflateReset2(&mut *self.strm, window_bits) } != zlib::Z_OK {
+            return Err(io::Error::new(ErrorKind::Other, "Error on reset the inflate stream"));
+        }
+
+        Ok(())
+    }
+
+    // Insert the bits, of the partial byte, used by the next block
+    fn prime(&mut self, bits: u8, value: u8) -> io::Result<()> {
+
+        if unsafe { zlib::inflatePrime(&mut *self.strm, bits as c_int, value as c_int) } != zlib::Z_OK {
+            return Err(io::Error::new(ErrorKind::Other, "Error on prime the inflate stream"));
+        }
+
+        Ok(())
+    }
+
+    // Restore the window of the stream
+    fn set_dictionary(&mut self, window: &[u8]) -> io::Result<()> {
+
+        let ret = unsafe {
+            zlib::inflateSetDictionary(&mut *self.strm, window.as_ptr(), window.len() as c_uint)
+        };
+
+        if ret != zlib::Z_OK {
+            return Err(io::Error::new(ErrorKind::Other, "Error on restore the inflate window"));
+        }
+
+        Ok(())
+    }
+
+    // Decompress the input on output. Return the consumed input, the
+    // produced output and the zlib return code
+    fn inflate(&mut self, input: &[u8], output: &mut [u8], flush: c_int) -> (usize, usize, c_int) {
+
+        self.strm.next_in = input.as_ptr() as *mut u8;
+        self.strm.avail_in = input.len() as c_uint;
+        self.strm.next_out = output.as_mut_ptr();
+        self.strm.avail_out = output.len() as c_uint;
+
+        let ret = unsafe { zlib::inflate(&mut *self.strm, flush) };
+
+        let consumed = input.len() - self.strm.avail_in as usize;
+        let produced = output.len() - self.strm.avail_out as usize;
+
+        self.strm.next_in = ptr::null_mut();
+        self.strm.avail_in = 0;
+        self.strm.next_out = ptr::null_mut();
+        self.strm.avail_out = 0;
+
+        (consumed, produced, ret)
+    }
+
+    fn data_type(&self) -> c_int {
+        self.strm.data_type
+    }
+}
+
+impl Drop for Inflater {
+
+    fn drop(&mut self) {
+        unsafe { zlib::inflateEnd(&mut *self.strm); }
+    }
+}
+
+// The compressed input with his buffer
+struct Stream<R: Read> {
+    reader: R,
+    inflater: Inflater,
+    buf: Vec<u8>,
+    pos: usize,
+    len: usize,
+    eof: bool
+}
+
+impl<R: Read> Stream<R> {
+
+    fn new(reader: R, inflater: Inflater) -> Stream<R> {
+        Stream {
+            reader: reader,
+            inflater: inflater,
+            buf: vec![0; CHUNK],
+            pos: 0,
+            len: 0,
+            eof: false
+        }
+    }
+
+    // Load more compressed input, if the buffer is empty
+    fn fill(&mut self) -> io::Result<()> {
+
+        if self.pos == self.len && !self.eof {
+            self.len = self.reader.read(&mut self.buf)?;
+            self.pos = 0;
+
+            if self.len == 0 {
+                self.eof = true;
+            }
+        }
+
+        Ok(())
+    }
+
+    // Return if there is more compressed input
+    fn has_input(&mut self) -> io::Result<bool> {
+        self.fill()?;
+        Ok(self.pos < self.len)
+    }
+
+    // Discard bytes of compressed input
+    fn skip(&mut self, mut size: u64) -> io::Result<()> {
+
+        while size > 0 {
+            if !self.has_input()? {
+                return Err(io::Error::new(ErrorKind::UnexpectedEof, "Unexpected end of the gzip file"));
+            }
+
+            let n = size.min((self.len - self.pos) as u64);
+            self.pos += n as usize;
+            size -= n;
+        }
+
+        Ok(())
+    }
+
+    // Run one step of the decompression. Return the consumed input,
+    // the produced output and the zlib return code
+    fn step(&mut self, output: &mut [u8], flush: c_int) -> io::Result<(usize, usize, c_int)> {
+
+        self.fill()?;
+
+        let (consumed, produced, ret) = self.inflater.inflate(&self.buf[self.pos..self.len], output, flush);
+
+        self.pos += consumed;
+
+        match ret {
+            zlib::Z_OK | zlib::Z_STREAM_END => Ok((consumed, produced, ret)),
+            zlib::Z_BUF_ERROR if self.eof => Err(io::Error::new(ErrorKind::UnexpectedEof, "Unexpected end of the gzip file")),
+            zlib::Z_BUF_ERROR => Ok((consumed, produced, ret)),
+            _ => Err(io::Error::new(ErrorKind::InvalidData, "Invalid gzip data"))
+        }
+    }
+}
+
+// Decompress a gzip stream and, at the same time, save the
+// checkpoints on the index
+pub struct GzIndexer<R: Read> {
+    stream: Stream<R>,
+    tree: Arc<Tree>,
+    window: Vec<u8>, // circular buffer with the last 32K of output
+    window_pos: usize,
+    total_in: u64,
+    total_out: u64,
+    last: u64, // output offset of the last checkpoint
+    member_end: bool,
+    done: bool
+}
+
+impl<R: Read> GzIndexer<R> {
+
+    pub fn new(reader: R, tree: Arc<Tree>) -> io::Result<GzIndexer<R>> {
+        Ok(GzIndexer {
+            stream: Stream::new(reader, Inflater::new(GZIP_WBITS)?),
+            tree: tree,
+            window: vec![0; WINSIZE],
+            window_pos: 0,
+            total_in: 0,
+            total_out: 0,
+            last: 0,
+            member_end: false,
+            done: false
+        })
+    }
+
+    // Keep the last 32K of output
+    fn push_window(&mut self, data: &[u8]) {
+
+        let data = if data.len() > WINSIZE {
+            &data[data.len() - WINSIZE..]
+        } else {
+            data
+        };
+
+        let first = data.len().min(WINSIZE - self.window_pos);
+
+        self.window[self.window_pos..self.window_pos + first].copy_from_slice(&data[..first]);
+        self.window[..data.len() - first].copy_from_slice(&data[first..]);
+
+        self.window_pos = (self.window_pos + data.len()) % WINSIZE;
+    }
+
+    // Save a checkpoint on the current position of the stream
+    fn checkpoint(&mut self, bits: u8) -> io::Result<()> {
+
+        let mut window = self.window[self.window_pos..].to_vec();
+        window.extend_from_slice(&self.window[..self.window_pos]);
+
+        let point = Checkpoint {
+            output: self.total_out,
+            input: self.total_in,
+            bits: bits,
+            window: window
+        };
+
+        if let Err(e) = self.tree.set(point.output.to_be_bytes().to_vec(), point.to_bytes()) {
+            return Err(io::Error::new(ErrorKind::Other, format!("Error on save the checkpoint: {}", e)));
+        }
+
+        self.last = self.total_out;
+
+        Ok(())
+    }
+}
+
+impl<R: Read> Read for GzIndexer<R> {
+
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+
+        if self.done || buf.is_empty() {
+            return Ok(0);
+        }
+
+        loop {
+            // We stop on each end of block for find the checkpoints
+            let (consumed, produced, ret) = match self.stream.step(buf, zlib::Z_BLOCK) {
+                Ok(step) => step,
+                Err(ref e) if self.member_end && e.kind() == ErrorKind::InvalidData => {
+                    warn!("Ignoring trailing garbage after the gzip data");
+                    self.done = true;
+                    return Ok(0);
+                },
+                Err(e) => return Err(e)
+            };
+
+            self.total_in += consumed as u64;
+            self.total_out += produced as u64;
+            self.push_window(&buf[..produced]);
+
+            if consumed > 0 || produced > 0 {
+                self.member_end = false;
+            }
+
+            if ret == zlib::Z_STREAM_END {
+                // End of a gzip member, but can exists another one
+                if self.stream.has_input()? {
+                    self.stream.inflater.reset(GZIP_WBITS)?;
+                    self.member_end = true;
+                } else {
+                    self.done = true;
+                }
+            } else {
+                let data_type = self.stream.inflater.data_type();
+
+                // End of a block, but not the last one
+                if data_type & 128 != 0 && data_type & 64 == 0 && self.total_out - self.last > SPAN {
+                    self.checkpoint((data_type & 7) as u8)?;
+                }
+            }
+
+            if produced > 0 || self.done {
+                return Ok(produced);
+            }
+        }
+    }
+}
+
+// Decompress a gzip file starting from the nearest checkpoint of
+// an uncompressed offset
+pub struct GzSeekReader {
+    stream: Stream<File>,
+    raw: bool, // started from a checkpoint, so we are without the gzip header
+    done: bool
+}
+
+impl GzSeekReader {
+
+    // Open the gzip file positioned on the uncompressed offset
+    pub fn open(path: &Path, tree: &Tree, offset: u64) -> io::Result<GzSeekReader> {
+
+        let mut file = File::open(path)?;
+
+        let point = match tree.get_lt((offset + 1).to_be_bytes().to_vec()) {
+            Ok(Some((key, val))) => Checkpoint::from_bytes(&key, &val),
+            _ => None
+        };
+
+        let mut start = 0;
+
+        let mut reader = if let Some(point) = point {
+
+            let mut inflater = Inflater::new(RAW_WBITS)?;
+
+            if point.bits > 0 {
+                file.seek(SeekFrom::Start(point.input - 1))?;
+
+                let mut byte = [0u8; 1];
+                file.read_exact(&mut byte)?;
+
+                inflater.prime(point.bits, byte[0] >> (8 - point.bits))?;
+            } else {
+                file.seek(SeekFrom::Start(point.input))?;
+            }
+
+            inflater.set_dictionary(&point.window)?;
+
+            start = point.output;
+
+            GzSeekReader {
+                stream: Stream::new(file, inflater),
+                raw: true,
+                done: false
+            }
+        } else {
+            GzSeekReader {
+                stream: Stream::new(file, Inflater::new(GZIP_WBITS)?),
+                raw: false,
+                done: false
+            }
+        };
+
+        copy(&mut Read::by_ref(&mut reader).take(offset - start), &mut sink())?;
+
+        Ok(reader)
+    }
+}
+
+impl Read for GzSeekReader {
+
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+
+        if self.done || buf.is_empty() {
+            return Ok(0);
+        }
+
+        loop {
+            let (_consumed, produced, ret) = self.stream.step(buf, zlib::Z_NO_FLUSH)?;
+
+            if ret == zlib::Z_STREAM_END {
+                // Without the header, the trailer of the member is our job
+                if self.raw {
+                    self.stream.skip(GZIP_TRAILER)?;
+                    self.raw = false;
+                }
+
+                if self.stream.has_input()? {
+                    self.stream.inflater.reset(GZIP_WBITS)?;
+                } else {
+                    self.done = true;
+                }
+            }
+
+            if produced > 0 || self.done {
+                return Ok(produced);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use std::env;
+    use std::fs;
+    use std::io::{Read, Write, copy, sink};
+    use std::path::PathBuf;
+    use std::process;
+
+    use flate2::Compression;
+    use flate2::write::GzEncoder;
+    use sled::Db;
+
+    use super::{GzIndexer, GzSeekReader, SPAN};
+
+    // Content that is not too compressible, so the deflate has many blocks
+    fn content(size: usize, seed: u64) -> Vec<u8> {
+
+        let mut state = seed;
+
+        (0..size).map(|i| {
+            state = state.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+
+            if i % 3 == 0 { b'a' + (state >> 59) as u8 } else { (state >> 33) as u8 }
+        }).collect()
+    }
+
+    fn gzip(data: &[u8]) -> Vec<u8> {
+
+        let mut encoder = GzEncoder::new(vec![], Compression::fast());
+
+        encoder.write_all(data).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    // Index the gzip file and compare the content readed from the
+    // offsets with the original
+    fn check(name: &str, members: &[Vec<u8>]) {
+
+        let dir = env::temp_dir().join(format!("blitze_gzindex_{}_{}", name, process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+
+        let path: PathBuf = dir.join("data.gz");
+        let original: Vec<u8> = members.concat();
+
+        let compressed: Vec<u8> = members.iter()
+            .flat_map(|member| gzip(member))
+            .collect();
+
+        fs::write(&path, &compressed).unwrap();
+
+        let db = Db::start_default(dir.join("db")).unwrap();
+        let tree = db.open_tree("seek").unwrap();
+
+        let mut indexed = vec![];
+        GzIndexer::new(&compressed[..], tree.clone()).unwrap()
+            .read_to_end(&mut indexed).unwrap();
+
+        assert!(indexed == original, "The indexer changed the content");
+
+        let points: Vec<u64> = tree.iter()
+            .map(|point| {
+                let (key, _val) = point.unwrap();
+                let mut output = [0u8; 8];
+                output.copy_from_slice(&key);
+                u64::from_be_bytes(output)
+            })
+            .collect();
+
+        assert!(points.len() as u64 >= original.len() as u64 / SPAN - 1, "Few checkpoints: {:?}", points);
+
+        let mut offsets = vec![0, 1, original.len() as u64 - 1, original.len() as u64];
+
+        for &point in &points {
+            offsets.extend_from_slice(&[point - 1, point, point + 1, point + 70000]);
+        }
+
+        // The end of each member
+        let mut end = 0;
+        for member in members {
+            end += member.len() as u64;
+            offsets.extend_from_slice(&[end - 1, end, end + 1]);
+        }
+
+        for offset in offsets.into_iter().filter(|&offset| offset <= original.len() as u64) {
+
+            let mut reader = GzSeekReader::open(&path, &tree, offset).unwrap();
+
+            let mut readed = vec![];
+            Read::by_ref(&mut reader).take(100000).read_to_end(&mut readed).unwrap();
+
+            let end = original.len().min(offset as usize + 100000);
+
+            assert!(readed[..] == original[offset as usize..end], "Different content on the offset {}", offset);
+
+            // The rest of the stream is valid until the end
+            let rest = copy(&mut reader, &mut sink()).unwrap();
+
+            assert_eq!(offset + readed.len() as u64 + rest, original.len() as u64);
+        }
+
+        drop(tree);
+        drop(db);
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn seek_single_member() {
+        check("single", &[content(2 * SPAN as usize + 12345, 1)]);
+    }
+
+    #[test]
+    fn seek_multi_member() {
+        check("multi", &[
+            content(SPAN as usize + 54321, 2),
+            content(1000, 3),
+            content(SPAN as usize + 777, 4)
+        ]);
+    }
+}
@@ -0,0 +1,223 @@
+///
+/// Blitz Explorer
+///
+/// Secondary indexes of the files, for the searches don't need
+/// read all indexed files. The names are indexed by his trigrams,
+/// by the full name and by the extension
+///
+/// Copyright 2019 Luis Fernando Batels <luisfbatels@gmail.com>
+///
+
+use std::path::Path;
+use std::sync::Arc;
+use std::collections::HashSet;
+
+use sled::{Db, Tree};
+
+use super::file::{File, FileTar};
+
+// Max of postings counted for choose the smaller lookup
+const ESTIMATE_CAP: usize = 10000;
+
+// Search answered by the indexes
+#[derive(Debug, Clone)]
+pub enum Lookup {
+    Name(String), // part of the name, with 3 chars or more
+    NamePrefix(String),
+    Ext(String)
+}
+
+pub struct SearchIndex {
+    archives: Arc<Tree>, // archives with the indexes
+    names: Arc<Tree>, // name \0 tar \0 path
+    trigrams: Arc<Tree>, // trigram \0 tar \0 path => name
+    exts: Arc<Tree> // ext \0 tar \0 path
+}
+
+impl SearchIndex {
+
+    pub fn new(db: &Db) -> SearchIndex {
+        SearchIndex {
+            archives: db.open_tree("search_archives")
+                .expect("Can't open the search archives tree"),
+            names: db.open_tree("search_names")
+                .expect("Can't open the search names tree"),
+            trigrams: db.open_tree("search_trigrams")
+                .expect("Can't open the search trigrams tree"),
+            exts: db.open_tree("search_exts")
+                .expect("Can't open the search exts tree")
+        }
+    }
+
+    // Return if the files of tar are on the indexes
+    pub fn has_archive(&self, tar: &FileTar) -> bool {
+        self.archives.get(tar.full_path.as_bytes()).unwrap_or(None).is_some()
+    }
+
+    pub fn set_archive(&self, tar: &FileTar) {
+        self.archives.set(tar.full_path.as_bytes(), vec![])
+            .expect("Error on set the search archive");
+    }
+
+    pub fn remove_archive(&self, tar: &FileTar) {
+        self.archives.del(tar.full_path.as_bytes())
+            .expect("Error on remove the search archive");
+    }
+
+    // Add the file on the indexes
+    pub fn add(&self, tar: &FileTar, file: &File) {
+
+        let name = file.file_name.to_lowercase();
+
+        self.names.set(SearchIndex::posting(&name, tar, file), vec![])
+            .expect("Error on set the search name");
+
+        for trigram in SearchIndex::trigrams(&name) {
+            self.trigrams.set(SearchIndex::posting(&trigram, tar, file), name.as_bytes().to_vec())
+                .expect("Error on set the search trigram");
+        }
+
+        if let Some(ext) = SearchIndex::ext(&name) {
+            self.exts.set(SearchIndex::posting(&ext, tar, file), vec![])
+                .expect("Error on set the search ext");
+        }
+    }
+
+    // Remove the file of the indexes
+    pub fn remove(&self, tar: &FileTar, file: &File) {
+
+        let name = file.file_name.to_lowercase();
+
+        self.names.del(SearchIndex::posting(&name, tar, file))
+            .expect("Error on remove the search name");
+
+        for trigram in SearchIndex::trigrams(&name) {
+            self.trigrams.del(SearchIndex::posting(&trigram, tar, file))
+                .expect("Error on remove the search trigram");
+        }
+
+        if let Some(ext) = SearchIndex::ext(&name) {
+            self.exts.del(SearchIndex::posting(&ext, tar, file))
+                .expect("Error on remove the search ext");
+        }
+    }
+
+    // Number of files of the lookup, until a max. Used for choose
+    // the lookup with less files
+    pub fn estimate(&self, lookup: &Lookup) -> usize {
+        match *lookup {
+            Lookup::Name(ref name) => {
+                SearchIndex::trigrams(name).iter()
+                    .map(|trigram| SearchIndex::count(&self.trigrams, trigram))
+                    .min()
+                    .unwrap_or(0)
+            },
+            Lookup::NamePrefix(ref prefix) => SearchIndex::count(&self.names, prefix),
+            Lookup::Ext(ref ext) => SearchIndex::count(&self.exts, &format!("{}\0", ext))
+        }
+    }
+
+    // The tar and path of the files of lookup. The results are readed
+    // from the index while iterated
+    pub fn lookup<'a>(&'a self, lookup: &Lookup) -> Box<dyn Iterator<Item = (FileTar, String)> + 'a> {
+        match *lookup {
+            Lookup::Name(ref name) => {
+                // The trigram with less files
+                let trigram = SearchIndex::trigrams(name).into_iter()
+                    .min_by_key(|trigram| SearchIndex::count(&self.trigrams, trigram))
+                    .unwrap_or_default();
+
+                let name = name.clone();
+
+                Box::new(SearchIndex::postings(&self.trigrams, format!("{}\0", trigram))
+                    .filter(move |&(_, ref fname)| fname.contains(name.as_str()))
+                    .filter_map(|(key, _)| SearchIndex::split(&key, 1)))
+            },
+            Lookup::NamePrefix(ref prefix) => {
+                let prefix = prefix.clone();
+
+                Box::new(SearchIndex::postings(&self.names, prefix.clone())
+                    .filter_map(move |(key, _)| {
+                        // The name is the key until the first \0
+                        key.splitn(2, '\0').next()
+                            .filter(|name| name.starts_with(prefix.as_str()))?;
+
+                        SearchIndex::split(&key, 1)
+                    }))
+            },
+            Lookup::Ext(ref ext) => {
+                Box::new(SearchIndex::postings(&self.exts, format!("{}\0", ext))
+                    .filter_map(|(key, _)| SearchIndex::split(&key, 1)))
+            }
+        }
+    }
+
+    // All indexed names, with the full path of tar and the path
+    // of file. Ordered by name
+    pub fn names<'a>(&'a self) -> Box<dyn Iterator<Item = (String, String, String)> + 'a> {
+        Box::new(SearchIndex::postings(&self.names, String::new())
+            .filter_map(|(key, _)| {
+                let mut fields = key.splitn(3, '\0');
+
+                match (fields.next(), fields.next(), fields.next()) {
+                    (Some(name), Some(tar), Some(full_path)) => Some((name.to_string(), tar.to_string(), full_path.to_string())),
+                    _ => None
+                }
+            }))
+    }
+
+    // Iterate the keys and values starting with the prefix
+    fn postings<'a>(tree: &'a Tree, prefix: String) -> impl Iterator<Item = (String, String)> + 'a {
+
+        tree.scan(prefix.as_bytes())
+            .filter_map(|val| val.ok())
+            .map(|(key, val)| (String::from_utf8_lossy(&key).to_string(), String::from_utf8_lossy(&val).to_string()))
+            .take_while(move |&(ref key, _)| key.starts_with(prefix.as_str()))
+    }
+
+    fn count(tree: &Tree, prefix: &str) -> usize {
+        SearchIndex::postings(tree, prefix.to_string())
+            .take(ESTIMATE_CAP)
+            .count()
+    }
+
+    // Tar and path of the posting, after the skiped fields
+    fn split(key: &str, skip: usize) -> Option<(FileTar, String)> {
+
+        let mut fields = key.splitn(skip + 2, '\0').skip(skip);
+
+        let tar = fields.next()?;
+        let full_path = fields.next()?;
+
+        Some((FileTar::from_path(Path::new(tar)), full_path.to_string()))
+    }
+
+    fn posting(term: &str, tar: &FileTar, file: &File) -> Vec<u8> {
+        format!("{}\0{}\0{}", term, tar.full_path, file.full_path).into_bytes()
+    }
+
+    // Sequences of 3 chars of the name
+    fn trigrams(name: &str) -> Vec<String> {
+
+        let chars: Vec<char> = name.chars().collect();
+
+        let mut trigrams = vec![];
+        let mut seen = HashSet::new();
+
+        for window in chars.windows(3) {
+
+            let trigram: String = window.iter().collect();
+
+            if seen.insert(trigram.clone()) {
+                trigrams.push(trigram);
+            }
+        }
+
+        trigrams
+    }
+
+    fn ext(name: &str) -> Option<String> {
+        Path::new(name).extension()
+            .map(|ext| ext.to_string_lossy().to_string())
+    }
+}
@@ -1,2 +1,9 @@
 pub mod catalog;
 pub mod file;
+pub mod gzindex;
+pub mod decoder;
+pub mod zipfile;
+pub mod digest;
+pub mod cache;
+pub mod index;
+pub mod contents;
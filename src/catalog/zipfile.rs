@@ -0,0 +1,138 @@
+///
+/// Blitz Explorer
+///
+/// Support for the zip files. The zip has a central directory, so
+/// we don't need decompress the entire file for index or extract
+///
+/// Copyright 2019 Luis Fernando Batels <luisfbatels@gmail.com>
+///
+
+use std::fs::File;
+use std::path::Path;
+use std::io::{self, Read, BufReader, Seek, SeekFrom, ErrorKind};
+
+use flate2::read::DeflateDecoder;
+use bzip2::read::BzDecoder;
+use time::Tm;
+use zip::ZipArchive;
+
+// Signatures of a local file header and of a empty zip file
+const LOCAL_HEADER_SIG: [u8; 4] = [b'P', b'K', 0x03, 0x04];
+const EMPTY_ZIP_SIG: [u8; 4] = [b'P', b'K', 0x05, 0x06];
+
+// Fixed size of the local file header
+const LOCAL_HEADER_SIZE: usize = 30;
+
+// Member of the zip file, from the central directory
+pub struct ZipEntry {
+    pub name: String,
+    pub is_dir: bool,
+    pub size: u64,
+    pub csize: u64, // compressed size
+    pub mtime: u64,
+    pub offset: u64, // offset of the local header
+    pub mode: Option<u32> // unix permissions, if created on unix
+}
+
+// Return if the file is a zip, by his magic bytes
+pub fn is_zip(path: &Path) -> bool {
+
+    let mut magic = [0u8; 4];
+
+    if let Ok(mut file) = File::open(path) {
+        if file.read_exact(&mut magic).is_ok() {
+            return is_zip_magic(&magic);
+        }
+    }
+
+    return false;
+}
+
+// Return if the first bytes of the content are of a zip
+pub fn is_zip_magic(magic: &[u8]) -> bool {
+    magic.starts_with(&LOCAL_HEADER_SIG) || magic.starts_with(&EMPTY_ZIP_SIG)
+}
+
+// Read the members of the zip file from his central directory
+pub fn entries(path: &Path) -> io::Result<Vec<ZipEntry>> {
+
+    let file = BufReader::new(File::open(path)?);
+
+    let mut archive = ZipArchive::new(file)
+        .map_err(|e| io::Error::new(ErrorKind::InvalidData, e.to_string()))?;
+
+    let mut entries = vec![];
+
+    for i in 0..archive.len() {
+
+        let member = archive.by_index_raw(i)
+            .map_err(|e| io::Error::new(ErrorKind::InvalidData, e.to_string()))?;
+
+        let modified = member.last_modified();
+
+        // The dos time don't have time zone, so we assume UTC
+        let mtime = Tm {
+            tm_sec: modified.second() as i32,
+            tm_min: modified.minute() as i32,
+            tm_hour: modified.hour() as i32,
+            tm_mday: modified.day() as i32,
+            tm_mon: modified.month() as i32 - 1,
+            tm_year: modified.year() as i32 - 1900,
+            tm_wday: 0,
+            tm_yday: 0,
+            tm_isdst: 0,
+            tm_utcoff: 0,
+            tm_nsec: 0
+        };
+
+        entries.push(ZipEntry {
+            name: member.name().to_string(),
+            is_dir: member.is_dir(),
+            size: member.size(),
+            csize: member.compressed_size(),
+            mtime: mtime.to_timespec().sec.max(0) as u64,
+            offset: member.header_start(),
+            mode: member.unix_mode()
+        });
+    }
+
+    Ok(entries)
+}
+
+// Open the decompressed content of a member, going directly to
+// his local header
+pub fn open_member(path: &Path, offset: u64, csize: u64) -> io::Result<Box<dyn Read>> {
+
+    let mut file = File::open(path)?;
+
+    file.seek(SeekFrom::Start(offset))?;
+
+    let mut header = [0u8; LOCAL_HEADER_SIZE];
+    file.read_exact(&mut header)?;
+
+    if header[..4] != LOCAL_HEADER_SIG {
+        return Err(io::Error::new(ErrorKind::InvalidData, "Invalid local header of zip member"));
+    }
+
+    let flags = u16::from_le_bytes([header[6], header[7]]);
+    let method = u16::from_le_bytes([header[8], header[9]]);
+    let name_len = u16::from_le_bytes([header[26], header[27]]) as i64;
+    let extra_len = u16::from_le_bytes([header[28], header[29]]) as i64;
+
+    if flags & 1 != 0 {
+        return Err(io::Error::new(ErrorKind::Other, "Encrypted zip members are not supported"));
+    }
+
+    file.seek(SeekFrom::Current(name_len + extra_len))?;
+
+    let data = BufReader::new(file).take(csize);
+
+    let content: Box<dyn Read> = match method {
+        0 => Box::new(data),
+        8 => Box::new(DeflateDecoder::new(data)),
+        12 => Box::new(BzDecoder::new(data)),
+        _ => return Err(io::Error::new(ErrorKind::Other, format!("Compression method {} of zip is not supported", method)))
+    };
+
+    Ok(content)
+}
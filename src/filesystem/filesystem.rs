@@ -9,23 +9,27 @@
 
 use std::ffi::OsStr;
 use std::collections::HashMap;
-use std::sync::{Arc, Mutex};
-use std::io::prelude::Read;
+use std::sync::Arc;
+use std::io::{Read, Seek, SeekFrom};
+use std::fs;
 
-use libc::ENOENT;
+use libc::{ENOENT, EISDIR, EIO, EBADF};
 use time::{self, Timespec};
-use fuse::{FileType, FileAttr, Filesystem, Request, ReplyEntry, ReplyAttr, ReplyDirectory, ReplyData};
+use fuse::{FileType, FileAttr, Filesystem, Request, ReplyEntry, ReplyAttr, ReplyDirectory, ReplyData, ReplyOpen, ReplyEmpty};
 
 use catalog::catalog::Catalog;
-use catalog::file::{File, FileTar};
+use catalog::file::{File, FileKind};
 
-pub struct TarInterface<'a> {
-    pub catalog: &'a mut Arc<Mutex<Catalog>>,
-    pub inodes: &'a mut HashMap<(u64, String), (u64, File)>, // (parent ino, name of file) => (ino of file, File)
-    pub itars: &'a mut HashMap<u64, FileTar> // ino of file => tar parent
+// Childs loaded from catalog per time on the listing of folders
+const READDIR_PAGE: usize = 256;
+
+pub struct TarInterface {
+    pub catalog: Arc<Catalog>,
+    pub handles: HashMap<u64, fs::File>, // fh => opened extracted file
+    pub last_fh: u64
 }
 
-impl<'a> TarInterface<'a> {
+impl TarInterface {
 
     // Build the default FileAttr values
     fn def_file_attr(ino: u64) -> FileAttr {
@@ -48,56 +52,122 @@ impl<'a> TarInterface<'a> {
         }
     }
 
-    // Build the default value for File
-    fn def_file(name: String, is_file: bool, ino: u64) -> File {
+    // Build the FileAttr values of a indexed file
+    fn file_attr(file: &File) -> FileAttr {
 
-        File {
-            full_path: name.clone(),
-            file_name: name.clone(),
-            mtime: 0,
-            size: 0,
-            is_file: is_file,
-            level_path: 1,
-            ino: ino
+        let mut attr = TarInterface::def_file_attr(file.ino);
+
+        attr.kind = TarInterface::file_type(file);
+        attr.mtime = Timespec::new(file.mtime as i64, 0);
+        attr.size = file.size;
+
+        // The size of a symlink is the size of his target path
+        if let Some(ref link_name) = file.link_name {
+            if file.kind == FileKind::Symlink {
+                attr.size = link_name.len() as u64;
+            }
+        }
+
+        // The original owner and permissions, if the archive has them
+        if file.mode != 0 {
+            attr.perm = file.mode as u16;
+        }
+
+        attr.uid = file.uid as u32;
+        attr.gid = file.gid as u32;
+
+        if attr.kind != FileType::Directory {
+            attr.nlink = 1;
+        }
+
+        if let (Some(major), Some(minor)) = (file.device_major, file.device_minor) {
+            attr.rdev = (minor & 0xff) | (major << 8) | ((minor & !0xff) << 12);
+        }
+
+        attr
+    }
+
+    // Type of the indexed file on the filesystem
+    fn file_type(file: &File) -> FileType {
+
+        if file.is_archive {
+            return FileType::Directory;
+        }
+
+        match file.kind {
+            FileKind::Symlink => FileType::Symlink,
+            FileKind::CharDevice => FileType::CharDevice,
+            FileKind::BlockDevice => FileType::BlockDevice,
+            FileKind::Fifo => FileType::NamedPipe,
+            _ if file.is_file => FileType::RegularFile,
+            _ => FileType::Directory
         }
     }
 }
 
-impl<'a> Filesystem for TarInterface<'a> {
-    
-    fn read(&mut self, _req: &Request, ino: u64, _fh: u64, offset: i64, size: u32, reply: ReplyData) {
+impl Filesystem for TarInterface {
 
-        for (_key, inode) in self.inodes.iter() {
-            if inode.0 == ino {
+    fn open(&mut self, _req: &Request, ino: u64, _flags: u32, reply: ReplyOpen) {
 
-                let file = &inode.1;
-                
-                if file.is_file {
+        let (tar, file) = match self.catalog.get_inode_file(ino) {
+            Some(inode) => inode,
+            None => {
+                reply.error(ENOENT);
+                return;
+            }
+        };
 
-                    let tar = self.itars.get(&ino)
-                        .expect("Tar not found");
+        if !file.is_file {
+            reply.error(EISDIR);
+            return;
+        }
 
-                    let mut catalog = self.catalog.lock()
-                        .expect("Error on lock the catalog for fuse");
+        // The extracted file stay open until the release
+        if let Some(extracted) = self.catalog.extract_file(&tar, &file) {
 
-                    if let Some(mut extracted) = catalog.extract_file(&tar, &file) {
-                        let mut buf = Vec::new();
+            self.last_fh = self.last_fh + 1;
+            self.handles.insert(self.last_fh, extracted);
 
-                        extracted.read_to_end(&mut buf)
-                            .expect("Error on read the tmp file");
+            reply.opened(self.last_fh, 0);
+        } else {
+            error!("Error on extract: {}", file.full_path.clone());
+            reply.error(EIO);
+        }
+    }
 
-                        let mut to = offset + size as i64;
-                    
-                        if to > buf.len() as i64 {
-                            to = buf.len() as i64;
-                        }
+    fn read(&mut self, _req: &Request, _ino: u64, fh: u64, offset: i64, size: u32, reply: ReplyData) {
 
-                        reply.data(&buf[offset as usize..to as usize]);
+        let extracted = match self.handles.get_mut(&fh) {
+            Some(extracted) => extracted,
+            None => {
+                reply.error(EBADF);
+                return;
+            }
+        };
 
-                    } else {
-                        error!("Error on extract: {}", file.full_path.clone());
-                    }
+        if extracted.seek(SeekFrom::Start(offset as u64)).is_err() {
+            error!("Error on seek the tmp file");
+            reply.error(EIO);
+            return;
+        }
+
+        let mut buf = Vec::with_capacity(size as usize);
+
+        if extracted.take(size as u64).read_to_end(&mut buf).is_err() {
+            error!("Error on read the tmp file");
+            reply.error(EIO);
+            return;
+        }
 
+        reply.data(&buf);
+    }
+
+    fn readlink(&mut self, _req: &Request, ino: u64, reply: ReplyData) {
+
+        if let Some((_tar, file)) = self.catalog.get_inode_file(ino) {
+            if let Some(link_name) = file.link_name {
+                if file.kind == FileKind::Symlink {
+                    reply.data(link_name.as_bytes());
                     return;
                 }
             }
@@ -106,29 +176,27 @@ impl<'a> Filesystem for TarInterface<'a> {
         reply.error(ENOENT);
     }
 
-    fn lookup(&mut self, _req: &Request, parent: u64, name_osstr: &OsStr, reply: ReplyEntry) {
+    fn release(&mut self, _req: &Request, _ino: u64, fh: u64, _flags: u32, _lock_owner: u64, _flush: bool, reply: ReplyEmpty) {
 
-        let name = name_osstr.to_str()
-            .expect("Error on OsStr to String")
-            .to_string();
+        self.handles.remove(&fh);
 
-        let ino_file_parent = (parent, TarInterface::def_file("".to_string(), false, 1));
+        reply.ok();
+    }
 
-        let (ino, file) = match self.inodes.get(&(parent, name)) {
-            Some(aux) => aux,
-            None => &ino_file_parent
-        };
+    fn lookup(&mut self, _req: &Request, parent: u64, name_osstr: &OsStr, reply: ReplyEntry) {
 
-        let mut attr = TarInterface::def_file_attr(*ino);
+        let name = name_osstr.to_str()
+            .expect("Error on OsStr to String");
 
-        if file.is_file {
-            attr.kind = FileType::RegularFile;
-        }
+        let inode = self.catalog.get_child_ino(parent, name)
+            .and_then(|ino| self.catalog.get_inode_file(ino));
 
-        attr.mtime = Timespec::new(file.mtime as i64, 0);
-        attr.size = file.size;
+        if let Some((_tar, file)) = inode {
+            reply.entry(&time::now().to_timespec(), &TarInterface::file_attr(&file), 0);
+            return;
+        }
 
-        reply.entry(&time::now().to_timespec(), &attr, 0);
+        reply.error(ENOENT);
     }
 
     fn getattr(&mut self, _req: &Request, ino: u64, reply: ReplyAttr) {
@@ -138,23 +206,9 @@ impl<'a> Filesystem for TarInterface<'a> {
             return;
         }
 
-        for (_key, inode) in self.inodes.iter() {
-            if inode.0 == ino {
-
-                let file = &inode.1;
-                let mut attr = TarInterface::def_file_attr(ino);
-
-                if file.is_file {
-                    attr.kind = FileType::RegularFile;
-                }
-
-                attr.mtime = Timespec::new(file.mtime as i64, 0);
-                attr.size = file.size;
-
-                reply.attr(&time::now().to_timespec(), &attr);
-
-                return;
-            }
+        if let Some((_tar, file)) = self.catalog.get_inode_file(ino) {
+            reply.attr(&time::now().to_timespec(), &TarInterface::file_attr(&file));
+            return;
         }
 
         reply.error(ENOENT);
@@ -162,58 +216,37 @@ impl<'a> Filesystem for TarInterface<'a> {
 
     fn readdir(&mut self, _req: &Request, ino: u64, _fh: u64, offset: i64, mut reply: ReplyDirectory) {
 
-        // TODO: Paginate this!
-        if offset == 0 {
-
-            let mut files: Vec<(File, FileTar)> = vec![];
-            let mut catalog = self.catalog.lock()
-                .expect("Error on lock the catalog for fuse");
+        // The offset is the position of the next child to list
+        let mut position = offset as u64;
 
-            if ino == 1 {
-                // Root dir
-                let mut ino_tar = 2;
+        loop {
+            // Root dir, inside a tar file or a internal folder
+            let page = self.catalog.get_childs_page(ino, position, READDIR_PAGE);
 
-                for tar in catalog.get_catalogs() {
-                    files.push((TarInterface::def_file(tar.file_name.clone(), false, ino_tar), tar));
-                    ino_tar = ino_tar + 1;
-                }
+            if page.is_empty() {
+                break;
+            }
 
-            } else if let Some(tar) = self.itars.get(&ino) {
-                // Inside a tar file or a internal folder
+            for (child_position, name, child) in page {
 
-                let catalog_files = catalog.get_catalog(tar);
+                position = child_position + 1;
 
-                if ino >= 20000 {
-                    let inos_filter = catalog.get_files_inos(ino);
-                    for file in catalog_files {
-                        if inos_filter.contains(&file.ino) {
-                            files.push((file, tar.clone()));
-                        }
-                    }
+                let kind = if ino == 1 {
+                    FileType::Directory
                 } else {
-                    for file in catalog_files {
-                        if file.level_path == 1 {
-                            files.push((file, tar.clone()));
-                        }
+                    match self.catalog.get_inode_file(child) {
+                        Some((_tar, file)) => TarInterface::file_type(&file),
+                        None => continue
                     }
-                }
-            }
-
-            for (entry, tar) in files {
+                };
 
-                let next_ino = entry.ino;
-
-                if entry.is_file {
-                    reply.add(next_ino, next_ino as i64, FileType::Directory, entry.file_name.clone());
-                } else {
-                    reply.add(next_ino, next_ino as i64, FileType::RegularFile, entry.file_name.clone());
+                // Buffer of reply is full, the next childs will be
+                // requested with a new offset
+                if reply.add(child, position as i64, kind, name) {
+                    reply.ok();
+                    return;
                 }
-
-                self.inodes.insert((ino, entry.file_name.clone()), (next_ino, entry));
-
-                self.itars.insert(next_ino, tar);
             }
-
         }
 
         reply.ok();
@@ -0,0 +1,535 @@
+///
+/// Blitz Explorer
+///
+/// HTTP API for the web tools: listings in JSON and the content
+/// of the files
+///
+/// Copyright 2019 Luis Fernando Batels <luisfbatels@gmail.com>
+///
+
+use std::io::Read;
+use std::str;
+use std::sync::Arc;
+use std::thread;
+use std::path::Path;
+use std::panic::{self, AssertUnwindSafe};
+
+use tiny_http::{Server, Request, Response, Header, Method, StatusCode};
+use serde_json::{self, Value};
+use time::{self, Timespec};
+
+use catalog::catalog::Catalog;
+use catalog::file::File;
+use tcp::query::Query;
+
+// Max of files returned by a search, the others are got with the
+// offset
+const SEARCH_LIMIT: usize = 1000;
+
+pub struct Api {
+}
+
+impl Api {
+
+    // Handle the requests with the workers, until the server stops
+    pub fn serve(server: Server, workers: usize, catalog: Arc<Catalog>) {
+
+        let server = Arc::new(server);
+
+        let threads: Vec<_> = (0..workers).map(|id| {
+
+            let server = server.clone();
+            let catalog = catalog.clone();
+
+            thread::spawn(move || {
+                loop {
+                    let request = match server.recv() {
+                        Ok(request) => request,
+                        Err(e) => {
+                            error!("Error on receive the http request on worker {}: {}", id, e);
+                            continue;
+                        }
+                    };
+
+                    // Errors of a request can't kill the worker
+                    if panic::catch_unwind(AssertUnwindSafe(|| Api::handle(request, &catalog))).is_err() {
+                        error!("Error on handle the http request on worker {}", id);
+                    }
+                }
+            })
+        }).collect();
+
+        for thread in threads {
+            thread.join()
+                .expect("Error on http worker");
+        }
+    }
+
+    fn handle(request: Request, catalog: &Catalog) {
+
+        info!("Handling {} {}...", request.method(), request.url());
+
+        if *request.method() != Method::Get && *request.method() != Method::Head {
+            Api::error(request, 405, "Method not allowed");
+            return;
+        }
+
+        let url = request.url().to_string();
+
+        let mut parts = url.splitn(2, '?');
+        let path = parts.next().unwrap_or("");
+        let params = parts.next().unwrap_or("");
+
+        if path == "/archives" || path == "/archives/" {
+            Api::archives(request, catalog);
+        } else if path == "/search" {
+            Api::search(request, catalog, params);
+        } else if path.starts_with("/archives/") {
+
+            // The name of archive, the resource and his path
+            let mut parts = path["/archives/".len()..].splitn(3, '/');
+
+            let name = Api::decode(parts.next().unwrap_or(""), false);
+
+            match (parts.next(), parts.next()) {
+                (Some("tree"), None) => Api::tree(request, catalog, &name, &Api::param(params, "path")),
+                (Some("files"), Some(full_path)) => Api::file(request, catalog, &name, &Api::decode(full_path, false)),
+                _ => Api::error(request, 404, "Not found")
+            }
+        } else {
+            Api::error(request, 404, "Not found");
+        }
+    }
+
+    // GET /archives
+    fn archives(request: Request, catalog: &Catalog) {
+
+        let archives: Vec<Value> = catalog.get_catalogs().into_iter()
+            .map(|tar| json!({
+                "name": tar.file_name,
+                "path": tar.full_path
+            }))
+            .collect();
+
+        Api::json(request, 200, &Value::Array(archives));
+    }
+
+    // GET /archives/{name}/tree?path=
+    fn tree(request: Request, catalog: &Catalog, name: &str, full_path: &str) {
+
+        let tar = match catalog.get_catalog_by_name(name) {
+            Some(tar) => tar,
+            None => {
+                Api::error(request, 404, "Archive not found");
+                return;
+            }
+        };
+
+        let folder = catalog.get_entry(&tar, full_path.trim_matches('/'))
+            .filter(|file| !file.is_file);
+
+        let folder = match folder {
+            Some(folder) => folder,
+            None => {
+                Api::error(request, 404, "Directory not found");
+                return;
+            }
+        };
+
+        let files: Vec<Value> = catalog.get_childs(folder.ino).into_iter()
+            .map(|(tar, file)| file.to_json(&tar))
+            .collect();
+
+        Api::json(request, 200, &Value::Array(files));
+    }
+
+    // GET /search?q=&offset=&limit=
+    fn search(request: Request, catalog: &Catalog, params: &str) {
+
+        let text = Api::param(params, "q");
+
+        let (offset, limit) = match (Api::number(params, "offset", 0), Api::number(params, "limit", SEARCH_LIMIT)) {
+            (Some(offset), Some(limit)) => (offset, limit.min(SEARCH_LIMIT)),
+            _ => {
+                Api::error(request, 400, "Invalid offset or limit");
+                return;
+            }
+        };
+
+        if text.trim().is_empty() {
+            Api::error(request, 400, "The query is empty");
+            return;
+        }
+
+        let query = match Query::parse(&text) {
+            Ok(query) => query,
+            Err(e) => {
+                Api::error(request, 400, &format!("Invalid query: {}", e));
+                return;
+            }
+        };
+
+        // Only the page is keeped, the search stops at his end
+        let mut skipped = 0;
+        let mut files = vec![];
+
+        query.search(catalog, |tar, file| {
+
+            if skipped < offset {
+                skipped += 1;
+                return true;
+            }
+
+            if files.len() < limit {
+                files.push(file.to_json(tar));
+            }
+
+            files.len() < limit
+        });
+
+        Api::json(request, 200, &Value::Array(files));
+    }
+
+    // GET /archives/{name}/files/{path}
+    fn file(request: Request, catalog: &Catalog, name: &str, full_path: &str) {
+
+        let tar = match catalog.get_catalog_by_name(name) {
+            Some(tar) => tar,
+            None => {
+                Api::error(request, 404, "Archive not found");
+                return;
+            }
+        };
+
+        // The links are downloaded with the content of his target and
+        // the archives inside of the archive with his own content
+        let file = catalog.get_file(&tar, full_path)
+            .and_then(|file| catalog.resolve_link(&tar, file));
+
+        let file = match file {
+            Some(ref file) if !file.is_file && !file.is_archive => {
+                Api::error(request, 400, "Not a file");
+                return;
+            },
+            Some(file) => file,
+            None => {
+                Api::error(request, 404, "File not found");
+                return;
+            }
+        };
+
+        let etag = Api::etag(&file);
+        let last_modified = Api::http_date(file.mtime);
+
+        // The client has the same content
+        let not_modified = Api::header_value(&request, "If-None-Match") == Some(etag.clone());
+
+        let mut headers = vec![
+            Api::header("Content-Type", Api::content_type(&file.file_name)),
+            Api::header("ETag", &etag),
+            Api::header("Accept-Ranges", "bytes")
+        ];
+
+        if let Some(ref last_modified) = last_modified {
+            headers.push(Api::header("Last-Modified", last_modified));
+        }
+
+        if not_modified {
+            let mut response = Response::empty(304);
+
+            for header in headers {
+                response.add_header(header);
+            }
+
+            Api::respond(request, response);
+            return;
+        }
+
+        // A part of the content, like the rest of a stopped download. If
+        // the client has other version, the content is sent complete
+        let range = Api::header_value(&request, "Range")
+            .filter(|_| {
+                Api::header_value(&request, "If-Range")
+                    .map_or(true, |version| version == etag || Some(version) == last_modified)
+            })
+            .map(|range| Api::range(&range, file.size));
+
+        match range {
+            Some(Ok(Some((start, length)))) => {
+
+                let extracted = match catalog.extract_range(&tar, &file, start, length) {
+                    Some(extracted) => extracted,
+                    None => {
+                        error!("Error on extract: {}:{}", name, full_path);
+                        Api::error(request, 500, "Error on extract the file");
+                        return;
+                    }
+                };
+
+                headers.push(Api::header("Content-Range", &format!("bytes {}-{}/{}", start, start + length - 1, file.size)));
+
+                Api::respond(request, Api::content(206, headers, extracted, length));
+                return;
+            },
+            Some(Err(())) => {
+                let response = Response::empty(416)
+                    .with_header(Api::header("Content-Range", &format!("bytes */{}", file.size)));
+
+                Api::respond(request, response);
+                return;
+            },
+            _ => {}
+        }
+
+        let extracted = match catalog.extract_file(&tar, &file) {
+            Some(extracted) => extracted,
+            None => {
+                error!("Error on extract: {}:{}", name, full_path);
+                Api::error(request, 500, "Error on extract the file");
+                return;
+            }
+        };
+
+        Api::respond(request, Api::content(200, headers, extracted, file.size));
+    }
+
+    // The start and length of the Range header, only of one range. The
+    // invalid ranges are ignored and the ranges out of the file are
+    // errors
+    fn range(value: &str, size: u64) -> Result<Option<(u64, u64)>, ()> {
+
+        let value = value.trim();
+
+        if !value.starts_with("bytes=") || value.contains(',') {
+            return Ok(None);
+        }
+
+        let mut parts = value["bytes=".len()..].splitn(2, '-');
+
+        let (first, last) = match (parts.next(), parts.next()) {
+            (Some(first), Some(last)) => (first.trim(), last.trim()),
+            _ => return Ok(None)
+        };
+
+        // Only the last bytes, like "-500"
+        if first.is_empty() {
+            return match last.parse::<u64>() {
+                Ok(length) if length > 0 && size > 0 => {
+                    let length = length.min(size);
+                    Ok(Some((size - length, length)))
+                },
+                Ok(_) => Err(()),
+                Err(_) => Ok(None)
+            };
+        }
+
+        let start = match first.parse::<u64>() {
+            Ok(start) => start,
+            Err(_) => return Ok(None)
+        };
+
+        let end = if last.is_empty() {
+            size
+        } else {
+            match last.parse::<u64>() {
+                Ok(last) if last >= start => last.saturating_add(1).min(size),
+                _ => return Ok(None)
+            }
+        };
+
+        if start >= size {
+            return Err(());
+        }
+
+        Ok(Some((start, end - start)))
+    }
+
+    fn header_value(request: &Request, name: &'static str) -> Option<String> {
+        request.headers().iter()
+            .find(|header| header.field.equiv(name))
+            .map(|header| header.value.as_str().to_string())
+    }
+
+
+    // The digest of the content, when known, or the file version
+    fn etag(file: &File) -> String {
+        match file.sha256 {
+            Some(ref sha256) => format!("\"{}\"", sha256),
+            None => format!("\"{:x}-{:x}-{:x}\"", file.ino, file.mtime, file.size)
+        }
+    }
+
+    fn http_date(mtime: u64) -> Option<String> {
+        time::at_utc(Timespec::new(mtime as i64, 0))
+            .strftime("%a, %d %b %Y %H:%M:%S GMT")
+            .ok()
+            .map(|date| date.to_string())
+    }
+
+    // Type of the content by the extension of the name
+    fn content_type(name: &str) -> &'static str {
+
+        let ext = Path::new(name).extension()
+            .map(|ext| ext.to_string_lossy().to_lowercase())
+            .unwrap_or_default();
+
+        match ext.as_str() {
+            "txt" | "log" | "md" | "csv" => "text/plain; charset=utf-8",
+            "html" | "htm" => "text/html; charset=utf-8",
+            "css" => "text/css",
+            "js" => "application/javascript",
+            "json" => "application/json",
+            "xml" => "application/xml",
+            "pdf" => "application/pdf",
+            "png" => "image/png",
+            "jpg" | "jpeg" => "image/jpeg",
+            "gif" => "image/gif",
+            "svg" => "image/svg+xml",
+            "webp" => "image/webp",
+            "mp3" => "audio/mpeg",
+            "ogg" => "audio/ogg",
+            "mp4" => "video/mp4",
+            "webm" => "video/webm",
+            "mkv" => "video/x-matroska",
+            "zip" => "application/zip",
+            "gz" => "application/gzip",
+            "tar" => "application/x-tar",
+            _ => "application/octet-stream"
+        }
+    }
+
+    // Value of the param on the query string of the url
+    fn param(params: &str, name: &str) -> String {
+        params.split('&')
+            .filter_map(|param| {
+                let mut parts = param.splitn(2, '=');
+
+                match (parts.next(), parts.next()) {
+                    (Some(key), value) if Api::decode(key, true) == name => Some(Api::decode(value.unwrap_or(""), true)),
+                    _ => None
+                }
+            })
+            .next()
+            .unwrap_or_default()
+    }
+
+    // Number of the query string, or the default without him
+    fn number(params: &str, name: &str, default: usize) -> Option<usize> {
+
+        let value = Api::param(params, name);
+
+        if value.is_empty() {
+            return Some(default);
+        }
+
+        value.parse::<usize>().ok()
+    }
+
+    // Decode the %XX of the url. On the query string the '+' is a space
+    fn decode(text: &str, plus: bool) -> String {
+
+        let bytes = text.as_bytes();
+
+        let mut decoded = vec![];
+        let mut i = 0;
+
+        while i < bytes.len() {
+
+            let hex = if bytes[i] == b'%' && i + 2 < bytes.len() && bytes[i + 1].is_ascii_hexdigit() && bytes[i + 2].is_ascii_hexdigit() {
+                str::from_utf8(&bytes[i + 1..i + 3]).ok()
+                    .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+            } else {
+                None
+            };
+
+            match hex {
+                Some(byte) => {
+                    decoded.push(byte);
+                    i += 3;
+                },
+                None => {
+                    decoded.push(if plus && bytes[i] == b'+' { b' ' } else { bytes[i] });
+                    i += 1;
+                }
+            }
+        }
+
+        String::from_utf8_lossy(&decoded).to_string()
+    }
+
+    fn header(name: &str, value: &str) -> Header {
+        Header::from_bytes(name.as_bytes(), value.as_bytes())
+            .expect("Invalid http header")
+    }
+
+    // Response with the content of a file. The length is always sent,
+    // without the chunked encoding of the big responses
+    fn content<R: Read>(status: u16, headers: Vec<Header>, content: R, length: u64) -> Response<R> {
+        Response::new(StatusCode(status), headers, content, Some(length as usize), None)
+            .with_chunked_threshold(usize::MAX)
+    }
+
+    fn json(request: Request, status: u16, value: &Value) {
+
+        let body = serde_json::to_string(value)
+            .expect("Error on Serialize the response");
+
+        let response = Response::from_string(body)
+            .with_status_code(status)
+            .with_header(Api::header("Content-Type", "application/json"));
+
+        Api::respond(request, response);
+    }
+
+    fn error(request: Request, status: u16, message: &str) {
+        Api::json(request, status, &json!({ "error": message }));
+    }
+
+    fn respond<R: Read>(request: Request, response: Response<R>) {
+
+        let url = request.url().to_string();
+
+        if let Err(e) = request.respond(response) {
+            error!("Error on send the response of {}: {}", url, e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::Api;
+
+    #[test]
+    fn ranges() {
+        assert_eq!(Api::range("bytes=0-99", 1000), Ok(Some((0, 100))));
+        assert_eq!(Api::range("bytes=100-", 1000), Ok(Some((100, 900))));
+        assert_eq!(Api::range("bytes=-100", 1000), Ok(Some((900, 100))));
+        assert_eq!(Api::range(" bytes=999-999 ", 1000), Ok(Some((999, 1))));
+
+        // The end and the last bytes are limited by the size
+        assert_eq!(Api::range("bytes=500-9999", 1000), Ok(Some((500, 500))));
+        assert_eq!(Api::range("bytes=-2000", 1000), Ok(Some((0, 1000))));
+    }
+
+    #[test]
+    fn ranges_out_of_file() {
+        assert_eq!(Api::range("bytes=1000-", 1000), Err(()));
+        assert_eq!(Api::range("bytes=2000-3000", 1000), Err(()));
+        assert_eq!(Api::range("bytes=-0", 1000), Err(()));
+        assert_eq!(Api::range("bytes=0-", 0), Err(()));
+        assert_eq!(Api::range("bytes=-10", 0), Err(()));
+    }
+
+    // The content is sent complete
+    #[test]
+    fn ranges_ignored() {
+        assert_eq!(Api::range("bytes=0-99,200-299", 1000), Ok(None));
+        assert_eq!(Api::range("bytes=5-2", 1000), Ok(None));
+        assert_eq!(Api::range("bytes=abc-", 1000), Ok(None));
+        assert_eq!(Api::range("bytes=0-abc", 1000), Ok(None));
+        assert_eq!(Api::range("bytes=-abc", 1000), Ok(None));
+        assert_eq!(Api::range("bytes=10", 1000), Ok(None));
+        assert_eq!(Api::range("items=0-99", 1000), Ok(None));
+    }
+}
@@ -13,9 +13,10 @@ use std::fs;
 use std::ffi::OsStr;
 use std::env;
 use std::panic;
+use std::process;
 use std::thread;
 use std::net::TcpListener;
-use std::sync::{Arc, Mutex};
+use std::sync::Arc;
 use std::collections::HashMap;
 
 #[macro_use]
@@ -25,10 +26,21 @@ extern crate notify;
 extern crate flate2;
 extern crate sled;
 extern crate serde;
+#[macro_use]
+extern crate serde_json;
 extern crate tar;
 extern crate fuse;
 extern crate libc;
 extern crate time;
+extern crate libz_sys;
+extern crate bzip2;
+extern crate xz2;
+extern crate zstd;
+extern crate zip;
+extern crate sha2;
+extern crate regex;
+extern crate glob;
+extern crate tiny_http;
 
 use simplelog::{SimpleLogger, LevelFilter, Config};
 use sled::Db;
@@ -38,15 +50,26 @@ use fuse::mount;
 mod catalog;
 mod tcp;
 mod filesystem;
+mod http;
 
 use catalog::catalog::Catalog;
+use catalog::cache::Cache;
 use catalog::file::FileTar;
-use tcp::request::Request;
+use tcp::pool::Pool;
+use tcp::query::Query;
+use http::api::Api;
 use filesystem::filesystem::TarInterface;
 
 const DB_INDEX: &str = "/var/db/blitze";
 const TCP_BIND: &str = "127.0.0.1:3355";
-const CACHE_EXTRACT: &str = "/tmp";
+const HTTP_BIND: &str = "127.0.0.1:3356";
+const CACHE_EXTRACT: &str = "/tmp/blitze";
+const CACHE_MAX_SIZE: u64 = 1024; // MiB
+const NESTED_DEPTH: usize = 0;
+const CONTENTS_MAX: u64 = 1024; // KiB
+const TCP_WORKERS: usize = 8;
+const TCP_BACKLOG: usize = 64;
+const TCP_TIMEOUT: u64 = 60; // seconds
 
 fn main() {
 
@@ -57,6 +80,23 @@ fn main() {
         error!("{}", e);
     }));
 
+    // Subcommands for verify the indexed archives, report the
+    // duplicated files and search, without start the servers
+    if env::args().nth(1) == Some("verify".to_string()) {
+        verify(env::args().skip(2).collect());
+        return;
+    }
+
+    if env::args().nth(1) == Some("duplicates".to_string()) {
+        duplicates(env::args().skip(2).collect());
+        return;
+    }
+
+    if env::args().nth(1) == Some("search".to_string()) {
+        search(env::args().skip(2).collect());
+        return;
+    }
+
     let input_folder_str = env::args().nth(1)
         .expect("Argument 1 needs to be the input folder");
 
@@ -64,11 +104,51 @@ fn main() {
         .expect("Argument 2 needs to be the mountpoint of the output content");
 
     let mut only_run: Option<bool> = None; // true = tcp, false = fuse, none = both
-    if let Some(on) = env::args().nth(3) {
-        if on == "--only-tcp" {
+    let mut nested_depth = NESTED_DEPTH;
+    let mut tcp_workers = TCP_WORKERS;
+    let mut tcp_backlog = TCP_BACKLOG;
+    let mut tcp_timeout = TCP_TIMEOUT;
+    let mut hash_contents = true;
+    let mut cache_dir = CACHE_EXTRACT.to_string();
+    let mut cache_max_size = CACHE_MAX_SIZE;
+    let mut index_contents = false;
+    let mut contents_max = CONTENTS_MAX;
+
+    for option in env::args().skip(3) {
+        if option == "--only-tcp" {
             only_run = Some(true);
-        } else if on == "--only-fuse" {
+        } else if option == "--only-fuse" {
             only_run = Some(false);
+        } else if option == "--no-hash-contents" {
+            hash_contents = false;
+        } else if option == "--index-contents" {
+            index_contents = true;
+        } else if option.starts_with("--index-contents-max=") {
+            contents_max = option.replacen("--index-contents-max=", "", 1)
+                .parse::<u64>()
+                .expect("Invalid value of --index-contents-max");
+        } else if cache_option(&option, &mut cache_dir, &mut cache_max_size) {
+            continue;
+        } else if option.starts_with("--nested-depth=") {
+            nested_depth = option.replacen("--nested-depth=", "", 1)
+                .parse::<usize>()
+                .expect("Invalid value of --nested-depth");
+        } else if option.starts_with("--tcp-workers=") {
+            tcp_workers = option.replacen("--tcp-workers=", "", 1)
+                .parse::<usize>()
+                .ok()
+                .filter(|&workers| workers > 0)
+                .expect("Invalid value of --tcp-workers, needs be at least 1");
+        } else if option.starts_with("--tcp-backlog=") {
+            tcp_backlog = option.replacen("--tcp-backlog=", "", 1)
+                .parse::<usize>()
+                .expect("Invalid value of --tcp-backlog");
+        } else if option.starts_with("--tcp-timeout=") {
+            tcp_timeout = option.replacen("--tcp-timeout=", "", 1)
+                .parse::<u64>()
+                .ok()
+                .filter(|&timeout| timeout > 0)
+                .expect("Invalid value of --tcp-timeout, needs be at least 1");
         }
     }
 
@@ -85,17 +165,26 @@ fn main() {
     let tcp_listener = TcpListener::bind(TCP_BIND)
         .expect("Error on bind the tcp port");
 
-    let catalog = Arc::new(Mutex::new(Catalog {
-        db: db,
-        cache_extract: CACHE_EXTRACT.to_string()
-    }));
+    let http_server = tiny_http::Server::http(HTTP_BIND)
+        .expect("Error on bind the http port");
+
+    // The catalog is shared without a global lock, only the indexing
+    // of a tar locks his own content
+    let cache = Cache::new(db.clone(), cache_dir, cache_max_size * 1024 * 1024);
+
+    // The words of the contents are indexed only if asked
+    if !index_contents {
+        contents_max = 0;
+    }
+
+    let catalog = Arc::new(Catalog::new(db, cache, nested_depth, hash_contents, contents_max * 1024));
 
     // Index all current content
     for entry in input_folder {
         let file_tar = entry
             .expect("Error on get the entry");
 
-        catalog.lock().unwrap().catalog_file(&file_tar.path());
+        catalog.catalog_file(&file_tar.path());
     }
 
     // Index all new, or changed, content
@@ -109,9 +198,6 @@ fn main() {
             let change = rx.recv()
                 .expect("Error on recv the change event");
 
-            let mut catalog_aux = catalog_indx.lock()
-                .expect("Error on lock the catalog for file system");
-
             let (change_path, burn_path): (Option<PathBuf>, Option<PathBuf>) = match change {
                 // New file
                 DebouncedEvent::Create(pb) => (Some(pb.clone()), Some(pb)),
@@ -126,12 +212,12 @@ fn main() {
 
             // In some cases we need remove the indexed content
             if let Some(path_buf) = burn_path {
-                catalog_aux.burn_catalog(&FileTar::from_path(path_buf.as_path()));
+                catalog_indx.burn_catalog(&FileTar::from_path(path_buf.as_path()));
             }
 
             // Indexing the new content
             if let Some(path_buf) = change_path {
-                catalog_aux.catalog_file(&path_buf.as_path());
+                catalog_indx.catalog_file(path_buf.as_path());
             }
         }
     });
@@ -145,19 +231,33 @@ fn main() {
             }
         }
 
-        info!("Waiting for tcp connections in {}...", TCP_BIND);
+        let pool = Pool::new(tcp_workers, tcp_backlog, Duration::from_secs(tcp_timeout), catalog_tcp);
+
+        info!("Waiting for tcp connections in {} with {} workers...", TCP_BIND, tcp_workers);
         for stream in tcp_listener.incoming() {
 
-            let client = stream.expect("Error on handle the tcp client");
+            match stream {
+                Ok(client) => pool.handle(client),
+                Err(e) => error!("Error on accept the tcp client: {}", e)
+            }
+        }
+    });
 
-            let mut catalog_aux = catalog_tcp.lock()
-                .expect("Error on lock the catalog for tcp server");
+    // The http api runs with the tcp server
+    let catalog_http = catalog.clone();
+    let thread_http = thread::spawn(move || {
 
-            Request::handle(client, &mut catalog_aux);
+        if let Some(on) = only_run {
+            if !on {
+                return;
+            }
         }
+
+        info!("Waiting for http requests in {} with {} workers...", HTTP_BIND, tcp_workers);
+        Api::serve(http_server, tcp_workers, catalog_http);
     });
 
-    let mut catalog_fs = catalog.clone();
+    let catalog_fs = catalog.clone();
     let thread_fs = thread::spawn(move || {
         
         if let Some(on) = only_run {
@@ -167,9 +267,9 @@ fn main() {
         }
 
         let tar_interface = TarInterface {
-            catalog: &mut catalog_fs,
-            inodes: &mut HashMap::new(),
-            itars: &mut HashMap::new()
+            catalog: catalog_fs,
+            handles: HashMap::new(),
+            last_fh: 0
         };
 
         let options: Vec<&OsStr> = vec![OsStr::new("-o"), OsStr::new("ro"), OsStr::new("-o"), OsStr::new("fsname=blitze")];
@@ -182,6 +282,116 @@ fn main() {
         .expect("Error on indexer thread");
     thread_tcp.join()
         .expect("Error on tcp thread");
+    thread_http.join()
+        .expect("Error on http thread");
     thread_fs.join()
         .expect("Error on fuse thread");
 }
+
+// Read the options of the cache. Returns false for the others options
+fn cache_option(option: &str, cache_dir: &mut String, cache_max_size: &mut u64) -> bool {
+
+    if option.starts_with("--cache-dir=") {
+        *cache_dir = option.replacen("--cache-dir=", "", 1);
+    } else if option.starts_with("--cache-max-size=") {
+        *cache_max_size = option.replacen("--cache-max-size=", "", 1)
+            .parse::<u64>()
+            .expect("Invalid value of --cache-max-size");
+    } else {
+        return false;
+    }
+
+    true
+}
+
+// Catalog of the subcommands, with the same cache of the server. The
+// options of the cache are removed from the args
+fn subcommand_catalog(args: &mut Vec<String>) -> Catalog {
+
+    let mut cache_dir = CACHE_EXTRACT.to_string();
+    let mut cache_max_size = CACHE_MAX_SIZE;
+
+    args.retain(|option| !cache_option(option, &mut cache_dir, &mut cache_max_size));
+
+    let db = Db::start_default(DB_INDEX)
+        .expect("Error on start the index database");
+
+    let cache = Cache::new(db.clone(), cache_dir, cache_max_size * 1024 * 1024);
+
+    Catalog::new(db, cache, NESTED_DEPTH, false, 0)
+}
+
+// Verify the content of the archives, by his name, with the digest
+// of the indexing. The database is locked by the server, so he can't
+// be running
+fn verify(mut archives: Vec<String>) {
+
+    let catalog = subcommand_catalog(&mut archives);
+
+    let mut all_ok = true;
+
+    for archive in archives {
+
+        let tar = catalog.get_catalog_by_name(&archive)
+            .or_else(|| catalog.get_catalogs().into_iter().find(|tar| tar.full_path == archive));
+
+        let failed = tar.and_then(|tar| catalog.verify(&tar));
+
+        if failed.is_none() {
+            println!("{}: not indexed", archive);
+            all_ok = false;
+            continue;
+        }
+
+        for (full_path, problem) in failed.unwrap() {
+            println!("{}:{}: {}", archive, full_path, problem);
+            all_ok = false;
+        }
+    }
+
+    if !all_ok {
+        process::exit(1);
+    }
+}
+
+// Print the groups of files with the same content on the indexed
+// archives and the bytes wasted by the copies
+fn duplicates(mut options: Vec<String>) {
+
+    let catalog = subcommand_catalog(&mut options);
+
+    let mut wasted_total = 0;
+
+    for (sha256, size, files) in catalog.get_duplicates() {
+
+        let wasted = size * (files.len() as u64 - 1);
+        wasted_total = wasted_total + wasted;
+
+        println!("{} size: {} copies: {} wasted: {}", sha256, size, files.len(), wasted);
+
+        for (tar, full_path) in files {
+            println!("  {}:{}", tar.file_name, full_path);
+        }
+    }
+
+    println!("wasted: {}", wasted_total);
+}
+
+// Print the indexed files that match with the query
+fn search(mut args: Vec<String>) {
+
+    let catalog = subcommand_catalog(&mut args);
+
+    let query = match Query::parse(&args.join(" ")) {
+        Ok(query) => query,
+        Err(e) => {
+            println!("Invalid query: {}", e);
+            process::exit(1);
+        }
+    };
+
+    query.search(&catalog, |tar, file| {
+        println!("{}:{}", tar.file_name, file.full_path);
+        true
+    });
+}
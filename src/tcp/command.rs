@@ -0,0 +1,350 @@
+///
+/// Blitz Explorer
+///
+/// Commands of the tcp protocol. They are parsed and executed here,
+/// and each version of the protocol formats his results
+///
+/// Copyright 2019 Luis Fernando Batels <luisfbatels@gmail.com>
+///
+
+use std::io::Read;
+
+use catalog::catalog::Catalog;
+use catalog::cache::CacheStats;
+use catalog::file::{File, FileTar};
+use catalog::contents;
+use tcp::query::Query;
+use tcp::fuzzy::{Fuzzy, FUZZY_TOP};
+
+pub enum Command {
+    Search(Query),
+    Fuzzy(String, usize), // text and max of results
+    Grep(String),
+    Download(String), // archive:path
+    DownloadRange(String, u64, u64), // archive:path, offset and length
+    DownloadByHash(String),
+    Stat(String),
+    Archives,
+    Ls(String),
+    Verify(String), // name of archive
+    Duplicates,
+    CacheStats
+}
+
+// One result of the listings, sent while found
+pub enum Item<'a> {
+    File(&'a FileTar, &'a File), // search, stat and ls
+    Scored(f64, &'a FileTar, &'a File), // fuzzy
+    Line(&'a FileTar, &'a File, usize, &'a str), // grep
+    Archive(&'a FileTar),
+    Problem(&'a FileTar, &'a str, &'a str), // verify: path and problem
+    Duplicate(&'a str, u64, &'a [(FileTar, String)]), // sha256, size and copies
+    CacheStats(&'a CacheStats)
+}
+
+// Content of the downloads
+pub struct Download {
+    pub file: File, // for the downloads by hash, one of the files
+    pub start: u64,
+    pub length: u64,
+    pub content: Box<dyn Read>
+}
+
+pub enum Failure {
+    InvalidCommand(String),
+    InvalidQuery(String),
+    NotAFile(String),
+    NotADirectory(String),
+    UnknownArchive(String),
+    UnknownPath(String, String), // archive and path
+    UnknownHash(String),
+    InvalidRange(u64), // size of the file
+    ExtractionFailed(String)
+}
+
+impl Failure {
+
+    // Description of the error for the client
+    pub fn message(&self) -> String {
+        match *self {
+            Failure::InvalidCommand(ref message) => message.clone(),
+            Failure::InvalidQuery(ref message) => message.clone(),
+            Failure::NotAFile(ref arg) => format!("Not a file: {}", arg),
+            Failure::NotADirectory(ref arg) => format!("Not a directory: {}", arg),
+            Failure::UnknownArchive(ref name) => format!("Archive not indexed: {}", name),
+            Failure::UnknownPath(ref name, ref full_path) => format!("Path not found on {}: {}", name, full_path),
+            Failure::UnknownHash(ref sha256) => format!("Content not indexed: {}", sha256),
+            Failure::InvalidRange(size) => format!("The file has {} bytes", size),
+            Failure::ExtractionFailed(ref arg) => format!("Error on extract: {}", arg)
+        }
+    }
+}
+
+impl Command {
+
+    // Parse the line of the command, without the /v2 prefix
+    pub fn parse(line: &str) -> Result<Command, Failure> {
+
+        let line = line.trim_end_matches(|c| c == '\n' || c == '\r');
+
+        let mut parts = line.trim_start_matches('/').splitn(2, '/');
+
+        let name = parts.next().unwrap_or("");
+        let arg = parts.next().unwrap_or("").trim();
+
+        let command = match name {
+            "search" => Command::Search(Command::query(arg)?),
+            "fuzzy" => Command::fuzzy(arg)?,
+            "grep" => {
+                if contents::words(arg).is_empty() {
+                    return Err(Failure::InvalidQuery("The text needs a word".to_string()));
+                }
+
+                Command::Grep(arg.to_string())
+            },
+            "download" => Command::Download(Command::required(arg)?),
+            "download-range" => Command::download_range(arg)?,
+            "download-by-hash" => Command::DownloadByHash(Command::required(arg)?.to_lowercase()),
+            "stat" => Command::Stat(Command::required(arg)?),
+            "archives" => Command::Archives,
+            "ls" => Command::Ls(Command::required(arg)?),
+            "verify" => Command::Verify(Command::required(arg)?),
+            "duplicates" => Command::Duplicates,
+            "cache-stats" => Command::CacheStats,
+            _ => return Err(Failure::InvalidCommand(format!("Unknown command: {}", name)))
+        };
+
+        Ok(command)
+    }
+
+    // Run the command. The results of listings are given to the found
+    // function, until he returns false, and the downloads return his
+    // content
+    pub fn run<F>(&self, catalog: &Catalog, mut found: F) -> Result<Option<Download>, Failure> where F: FnMut(Item) -> bool {
+
+        match *self {
+            Command::Search(ref query) => {
+                query.search(catalog, |tar, file| found(Item::File(tar, file)));
+            },
+            Command::Fuzzy(ref text, top) => {
+                for (score, tar, file) in Fuzzy::new(text, top).search(catalog) {
+                    if !found(Item::Scored(score, &tar, &file)) {
+                        break;
+                    }
+                }
+            },
+            Command::Grep(ref text) => {
+                catalog.grep(text, |tar, file, number, line| found(Item::Line(tar, file, number, line)));
+            },
+            Command::Download(ref arg) => {
+
+                let (tar, file) = Command::find_download(catalog, arg)?;
+
+                let extracted = catalog.extract_file(&tar, &file)
+                    .ok_or_else(|| Failure::ExtractionFailed(arg.clone()))?;
+
+                return Ok(Some(Download {
+                    start: 0,
+                    length: file.size,
+                    file: file,
+                    content: Box::new(extracted)
+                }));
+            },
+            Command::DownloadRange(ref arg, start, length) => {
+
+                let (tar, file) = Command::find_download(catalog, arg)?;
+
+                if start > file.size {
+                    return Err(Failure::InvalidRange(file.size));
+                }
+
+                let length = length.min(file.size - start);
+
+                let extracted = catalog.extract_range(&tar, &file, start, length)
+                    .ok_or_else(|| Failure::ExtractionFailed(arg.clone()))?;
+
+                return Ok(Some(Download {
+                    start: start,
+                    length: length,
+                    file: file,
+                    content: extracted
+                }));
+            },
+            Command::DownloadByHash(ref sha256) => {
+
+                let file = catalog.get_by_hash(sha256).into_iter()
+                    .filter_map(|(tar, full_path)| catalog.get_file(&tar, &full_path))
+                    .next()
+                    .ok_or_else(|| Failure::UnknownHash(sha256.clone()))?;
+
+                let extracted = catalog.extract_by_hash(sha256)
+                    .ok_or_else(|| Failure::ExtractionFailed(sha256.clone()))?;
+
+                return Ok(Some(Download {
+                    start: 0,
+                    length: file.size,
+                    file: file,
+                    content: Box::new(extracted)
+                }));
+            },
+            Command::Stat(ref arg) => {
+
+                let (tar, file) = Command::find_entry(catalog, arg)?;
+
+                found(Item::File(&tar, &file));
+            },
+            Command::Archives => {
+                for tar in catalog.get_catalogs() {
+                    if !found(Item::Archive(&tar)) {
+                        break;
+                    }
+                }
+            },
+            Command::Ls(ref arg) => {
+
+                let (_tar, folder) = Command::find_entry(catalog, arg)?;
+
+                if folder.is_file {
+                    return Err(Failure::NotADirectory(arg.clone()));
+                }
+
+                for (tar, file) in catalog.get_childs(folder.ino) {
+                    if !found(Item::File(&tar, &file)) {
+                        break;
+                    }
+                }
+            },
+            Command::Verify(ref name) => {
+
+                let tar = Command::find_archive(catalog, name)?;
+
+                let failed = catalog.verify(&tar)
+                    .ok_or_else(|| Failure::UnknownArchive(name.clone()))?;
+
+                for (full_path, problem) in failed {
+                    if !found(Item::Problem(&tar, &full_path, &problem)) {
+                        break;
+                    }
+                }
+            },
+            Command::Duplicates => {
+                for (sha256, size, files) in catalog.get_duplicates() {
+                    if !found(Item::Duplicate(&sha256, size, &files)) {
+                        break;
+                    }
+                }
+            },
+            Command::CacheStats => {
+                found(Item::CacheStats(&catalog.cache.stats()));
+            }
+        }
+
+        Ok(None)
+    }
+
+    // The commands with a argument
+    fn required(arg: &str) -> Result<String, Failure> {
+
+        if arg.is_empty() {
+            return Err(Failure::InvalidCommand("The argument is empty".to_string()));
+        }
+
+        Ok(arg.to_string())
+    }
+
+    fn query(text: &str) -> Result<Query, Failure> {
+
+        if text.is_empty() {
+            return Err(Failure::InvalidCommand("The query is empty".to_string()));
+        }
+
+        Query::parse(text)
+            .map_err(|e| Failure::InvalidQuery(format!("Invalid query: {}", e)))
+    }
+
+    // The max of results can be changed with top:N
+    fn fuzzy(text: &str) -> Result<Command, Failure> {
+
+        let mut top = FUZZY_TOP;
+        let mut words = vec![];
+
+        for word in text.split_whitespace() {
+            match word.replacen("top:", "", 1).parse::<usize>() {
+                Ok(n) if word.starts_with("top:") => top = n,
+                _ => words.push(word)
+            }
+        }
+
+        if words.is_empty() {
+            return Err(Failure::InvalidCommand("The text is empty".to_string()));
+        }
+
+        Ok(Command::Fuzzy(words.join(" "), top))
+    }
+
+    // The range is OFFSET, until the end, or OFFSET+LENGTH, before of
+    // the archive:path
+    fn download_range(arg: &str) -> Result<Command, Failure> {
+
+        let mut slices = arg.splitn(2, '/');
+
+        let range = slices.next().unwrap_or("");
+        let arg = Command::required(slices.next().unwrap_or(""))?;
+
+        let mut range_slices = range.splitn(2, '+');
+
+        let start = range_slices.next()
+            .and_then(|start| start.parse::<u64>().ok());
+        let length = match range_slices.next() {
+            Some(length) => length.parse::<u64>().ok(),
+            None => Some(u64::max_value())
+        };
+
+        match (start, length) {
+            (Some(start), Some(length)) => Ok(Command::DownloadRange(arg, start, length)),
+            _ => Err(Failure::InvalidCommand(format!("Invalid range: {}", range)))
+        }
+    }
+
+    fn find_archive(catalog: &Catalog, name: &str) -> Result<FileTar, Failure> {
+        catalog.get_catalog_by_name(name)
+            .ok_or_else(|| Failure::UnknownArchive(name.to_string()))
+    }
+
+    // Find the indexed file or folder by the "archive:path" of the
+    // request, or the root by the "archive". The path can have others
+    // ':' for the archives inside archives
+    fn find_entry(catalog: &Catalog, arg: &str) -> Result<(FileTar, File), Failure> {
+
+        let mut slices = arg.splitn(2, ':');
+
+        let name = slices.next().unwrap_or("");
+        let full_path = slices.next().unwrap_or("");
+
+        let tar = Command::find_archive(catalog, name)?;
+
+        match catalog.get_entry(&tar, full_path) {
+            Some(file) => Ok((tar, file)),
+            None => Err(Failure::UnknownPath(name.to_string(), full_path.to_string()))
+        }
+    }
+
+    // Same of find_entry, but for the files with content. The links
+    // are downloaded with the content of his target and the archives
+    // inside of the archive with his own content
+    fn find_download(catalog: &Catalog, arg: &str) -> Result<(FileTar, File), Failure> {
+
+        let (tar, file) = Command::find_entry(catalog, arg)?;
+
+        let file = match catalog.resolve_link(&tar, file.clone()) {
+            Some(target) => target,
+            None => return Err(Failure::UnknownPath(tar.file_name, file.link_name.unwrap_or_default()))
+        };
+
+        if !file.is_file && !file.is_archive {
+            return Err(Failure::NotAFile(arg.to_string()));
+        }
+
+        Ok((tar, file))
+    }
+}
@@ -0,0 +1,222 @@
+///
+/// Blitz Explorer
+///
+/// Fuzzy search of the file names, for when the user don't remember
+/// the exact name. The results are ranked by how close is the name,
+/// the depth of the path and how recent is the archive
+///
+/// Copyright 2019 Luis Fernando Batels <luisfbatels@gmail.com>
+///
+
+use std::fs;
+use std::path::Path;
+use std::collections::{BinaryHeap, HashMap};
+use std::cmp::{self, Reverse};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use catalog::catalog::Catalog;
+use catalog::file::{File, FileTar};
+
+// Results returned by default
+pub const FUZZY_TOP: usize = 20;
+
+// Names bigger are not compared by edit distance
+const MAX_DISTANCE_LEN: usize = 64;
+
+// Points of each kind of match
+const EXACT: f64 = 100.0;
+const PREFIX: f64 = 60.0;
+const CONTAINS: f64 = 40.0;
+const DISTANCE: f64 = 30.0;
+const SUBSEQUENCE: f64 = 20.0;
+const RECENT: f64 = 10.0; // archive modified now
+const DEPTH: f64 = 1.0; // lost per folder
+
+pub struct Fuzzy {
+    needle: Vec<char>,
+    top: usize
+}
+
+impl Fuzzy {
+
+    pub fn new(text: &str, top: usize) -> Fuzzy {
+        Fuzzy {
+            needle: text.trim().to_lowercase().chars().collect(),
+            top: top
+        }
+    }
+
+    // Return the top files, with the bigger scores first
+    pub fn search(&self, catalog: &Catalog) -> Vec<(f64, FileTar, File)> {
+
+        if self.needle.is_empty() || self.top == 0 {
+            return vec![];
+        }
+
+        // Points of the recency of each archive
+        let mut recents: HashMap<String, f64> = HashMap::new();
+
+        // The smaller score on the top
+        let mut best: BinaryHeap<Reverse<(i64, String, String)>> = BinaryHeap::new();
+
+        // The names index has the name and the path, so we don't
+        // need read the indexed files
+        for (name, tar, full_path) in catalog.index.names() {
+
+            let score = match self.score_name(&name) {
+                Some(score) => score,
+                None => continue
+            };
+
+            let depth = full_path.trim_end_matches('/').matches(|c| c == '/' || c == ':').count();
+
+            let recent = *recents.entry(tar.clone())
+                .or_insert_with(|| Fuzzy::recent(&tar));
+
+            let score = score + recent - depth as f64 * DEPTH;
+
+            best.push(Reverse(((score * 1000.0) as i64, tar, full_path)));
+
+            if best.len() > self.top {
+                best.pop();
+            }
+        }
+
+        let mut results = vec![];
+
+        for Reverse((score, tar, full_path)) in best.into_sorted_vec() {
+
+            let tar = FileTar::from_path(Path::new(&tar));
+
+            if let Some(file) = catalog.get_file(&tar, &full_path) {
+                results.push((score as f64 / 1000.0, tar, file));
+            }
+        }
+
+        results
+    }
+
+    // Points of the name, or none if not similar
+    fn score_name(&self, name: &str) -> Option<f64> {
+
+        let name: Vec<char> = name.trim_end_matches('/').chars().collect();
+
+        if name == self.needle {
+            return Some(EXACT);
+        }
+
+        if name.starts_with(&self.needle) {
+            return Some(PREFIX);
+        }
+
+        if name.windows(self.needle.len()).any(|window| window == &self.needle[..]) {
+            return Some(CONTAINS);
+        }
+
+        let mut score: Option<f64> = None;
+
+        // Typos of the name, with or without the extension
+        if name.len() <= MAX_DISTANCE_LEN {
+
+            let stem = match name.iter().rposition(|&c| c == '.') {
+                Some(n) if n > 0 => &name[..n],
+                _ => &name[..]
+            };
+
+            let distance = cmp::min(Fuzzy::distance(&self.needle, &name), Fuzzy::distance(&self.needle, stem));
+            let max = cmp::max(1, self.needle.len() / 3);
+
+            if distance <= max {
+                score = Some(DISTANCE * (1.0 - distance as f64 / (max + 1) as f64));
+            }
+        }
+
+        // Chars of the text in order, like "mphoto" on "my_photo"
+        if let Some(span) = Fuzzy::subsequence(&self.needle, &name) {
+
+            let subsequence = SUBSEQUENCE * self.needle.len() as f64 / span as f64;
+
+            score = Some(score.map_or(subsequence, |score| score.max(subsequence)));
+        }
+
+        score
+    }
+
+    // Levenshtein distance between the texts
+    fn distance(a: &[char], b: &[char]) -> usize {
+
+        let mut previous: Vec<usize> = (0..b.len() + 1).collect();
+        let mut current = vec![0; b.len() + 1];
+
+        for i in 0..a.len() {
+
+            current[0] = i + 1;
+
+            for j in 0..b.len() {
+
+                let cost = if a[i] == b[j] { 0 } else { 1 };
+
+                current[j + 1] = cmp::min(cmp::min(previous[j + 1] + 1, current[j] + 1), previous[j] + cost);
+            }
+
+            previous.copy_from_slice(&current);
+        }
+
+        previous[b.len()]
+    }
+
+    // Size of the smaller part of the name with all chars of the
+    // needle in order, if exists
+    fn subsequence(needle: &[char], name: &[char]) -> Option<usize> {
+
+        let mut best: Option<usize> = None;
+
+        for start in 0..name.len() {
+
+            if name[start] != needle[0] {
+                continue;
+            }
+
+            let mut pos = start;
+            let mut found = 0;
+
+            while pos < name.len() && found < needle.len() {
+                if name[pos] == needle[found] {
+                    found = found + 1;
+                }
+                pos = pos + 1;
+            }
+
+            if found < needle.len() {
+                break;
+            }
+
+            let span = pos - start;
+
+            best = Some(best.map_or(span, |best| cmp::min(best, span)));
+        }
+
+        best
+    }
+
+    // Points of the archive by his last modification, losing the
+    // half per year
+    fn recent(tar: &str) -> f64 {
+
+        let mtime = fs::metadata(tar)
+            .and_then(|meta| meta.modified())
+            .ok()
+            .and_then(|mtime| mtime.duration_since(UNIX_EPOCH).ok());
+
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).ok();
+
+        match (mtime, now) {
+            (Some(mtime), Some(now)) => {
+                let years = now.as_secs().saturating_sub(mtime.as_secs()) as f64 / (365.0 * 86400.0);
+
+                RECENT * 0.5f64.powf(years)
+            },
+            _ => 0.0
+        }
+    }
+}
@@ -1 +1,6 @@
 pub mod request;
+pub mod pool;
+pub mod query;
+pub mod fuzzy;
+pub mod v2;
+pub mod command;
@@ -0,0 +1,135 @@
+///
+/// Blitz Explorer
+///
+/// Pool of workers for handle the tcp clients
+///
+/// Copyright 2019 Luis Fernando Batels <luisfbatels@gmail.com>
+///
+
+use std::net::TcpStream;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{sync_channel, SyncSender, Receiver};
+use std::thread;
+use std::time::Duration;
+use std::panic::{self, AssertUnwindSafe};
+
+use catalog::catalog::Catalog;
+use tcp::request::Request;
+
+pub struct Pool {
+    sender: SyncSender<TcpStream>
+}
+
+// Count of the open sessions. They keep his worker while open, so one
+// worker is always free for the clients without session
+pub struct Sessions {
+    open: AtomicUsize,
+    max: usize
+}
+
+// The place of a open session, given back when dropped
+pub struct Session<'a> {
+    sessions: &'a Sessions
+}
+
+impl Pool {
+
+    // Start the workers. The backlog is the max of connections
+    // waiting for a free worker
+    pub fn new(size: usize, backlog: usize, timeout: Duration, catalog: Arc<Catalog>) -> Pool {
+
+        let (sender, receiver) = sync_channel::<TcpStream>(backlog);
+        let receiver = Arc::new(Mutex::new(receiver));
+        let sessions = Arc::new(Sessions::new(size - 1));
+
+        for id in 0..size {
+
+            let receiver = receiver.clone();
+            let catalog = catalog.clone();
+            let sessions = sessions.clone();
+
+            thread::spawn(move || {
+                Pool::work(id, receiver, timeout, catalog, sessions);
+            });
+        }
+
+        Pool {
+            sender: sender
+        }
+    }
+
+    // Send the client to the next free worker. If the backlog is
+    // full, wait for a free place
+    pub fn handle(&self, conn: TcpStream) {
+
+        if self.sender.send(conn).is_err() {
+            error!("Error on send the client to the workers");
+        }
+    }
+
+    // Loop of the worker, handling one client per time
+    fn work(id: usize, receiver: Arc<Mutex<Receiver<TcpStream>>>, timeout: Duration, catalog: Arc<Catalog>, sessions: Arc<Sessions>) {
+
+        loop {
+            let conn = {
+                let receiver = receiver.lock()
+                    .expect("Error on lock the receiver of worker");
+
+                receiver.recv()
+            };
+
+            let conn = match conn {
+                Ok(conn) => conn,
+                Err(_) => {
+                    info!("Stopping the worker {}...", id);
+                    return;
+                }
+            };
+
+            // A stalled client can't hold the worker forever
+            if conn.set_read_timeout(Some(timeout)).is_err() || conn.set_write_timeout(Some(timeout)).is_err() {
+                error!("Error on set the timeouts of the client {:?}", conn);
+                continue;
+            }
+
+            // Errors of a request can't kill the worker
+            if panic::catch_unwind(AssertUnwindSafe(|| Request::handle(conn, &catalog, &sessions))).is_err() {
+                error!("Error on handle the client on worker {}", id);
+            }
+        }
+    }
+}
+
+impl Sessions {
+
+    pub fn new(max: usize) -> Sessions {
+        Sessions {
+            open: AtomicUsize::new(0),
+            max: max
+        }
+    }
+
+    // Take a place for a new session, if there is one free
+    pub fn start<'a>(&'a self) -> Option<Session<'a>> {
+
+        if self.open.fetch_add(1, Ordering::SeqCst) >= self.max {
+            self.open.fetch_sub(1, Ordering::SeqCst);
+            return None;
+        }
+
+        Some(Session {
+            sessions: self
+        })
+    }
+
+    pub fn max(&self) -> usize {
+        self.max
+    }
+}
+
+impl<'a> Drop for Session<'a> {
+    fn drop(&mut self) {
+        self.sessions.open.fetch_sub(1, Ordering::SeqCst);
+    }
+}
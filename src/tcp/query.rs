@@ -0,0 +1,622 @@
+///
+/// Blitz Explorer
+///
+/// Query language of the searches. The terms are separated by
+/// spaces and combined with AND, OR, NOT(or -) and parentheses:
+///
+/// ext:xlsx size>1M mtime>2019-01-01 archive:2019-* (*.pdf OR re:report_\d+)
+///
+/// Copyright 2019 Luis Fernando Batels <luisfbatels@gmail.com>
+///
+
+use std::path::Path;
+use std::collections::HashSet;
+
+use regex::{Regex, RegexBuilder};
+use glob::{Pattern, MatchOptions};
+use time;
+
+use catalog::catalog::Catalog;
+use catalog::file::{File, FileTar};
+use catalog::index::Lookup;
+
+#[derive(Debug)]
+pub enum Query {
+    And(Vec<Query>),
+    Or(Vec<Query>),
+    Not(Box<Query>),
+    Term(Term)
+}
+
+#[derive(Debug)]
+pub enum Term {
+    Name(String), // part of the name, or of the path if has a '/'
+    Glob(Pattern), // of the name, or of the path if has a '/'
+    PathGlob(Pattern),
+    Regex(Regex), // of the path
+    Ext(String),
+    Archive(Pattern),
+    Path(String), // prefix of the path
+    Size(Cmp, u64),
+    Mtime(Cmp, u64)
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum Cmp {
+    Lt,
+    Le,
+    Eq,
+    Ge,
+    Gt
+}
+
+#[derive(Debug, PartialEq)]
+enum Token {
+    Word(String),
+    Quoted(String),
+    Open,
+    Close
+}
+
+impl Query {
+
+    // Parse the text of query
+    pub fn parse(text: &str) -> Result<Query, String> {
+
+        let tokens = Query::tokenize(text)?;
+
+        if tokens.is_empty() {
+            return Err("Empty query".to_string());
+        }
+
+        let mut pos = 0;
+
+        let query = Query::parse_or(&tokens, &mut pos)?;
+
+        if pos < tokens.len() {
+            return Err("Unexpected ')'".to_string());
+        }
+
+        Ok(query)
+    }
+
+    // Return if the indexed file, of the tar, is a result of query
+    pub fn matches(&self, tar: &FileTar, file: &File) -> bool {
+        match *self {
+            Query::And(ref queries) => queries.iter().all(|query| query.matches(tar, file)),
+            Query::Or(ref queries) => queries.iter().any(|query| query.matches(tar, file)),
+            Query::Not(ref query) => !query.matches(tar, file),
+            Query::Term(ref term) => term.matches(tar, file)
+        }
+    }
+
+    // Search the files of query on the catalog. The secondary indexes
+    // are used when the query has a indexed term, otherwise all files
+    // are readed. The search stops when the found returns false
+    pub fn search<F>(&self, catalog: &Catalog, mut found: F) where F: FnMut(&FileTar, &File) -> bool {
+
+        if let Some(lookups) = self.lookups(catalog) {
+
+            // With more than one lookup, the file can be on many
+            let mut seen = HashSet::new();
+
+            for lookup in lookups.iter() {
+                for (tar, full_path) in catalog.index.lookup(lookup) {
+
+                    if lookups.len() > 1 && !seen.insert((tar.full_path.clone(), full_path.clone())) {
+                        continue;
+                    }
+
+                    if let Some(file) = catalog.get_file(&tar, &full_path) {
+                        if self.matches(&tar, &file) && !found(&tar, &file) {
+                            return;
+                        }
+                    }
+                }
+            }
+
+            return;
+        }
+
+        for tar in catalog.get_catalogs() {
+
+            for file in catalog.get_catalog(&tar) {
+
+                if self.matches(&tar, &file) && !found(&tar, &file) {
+                    return;
+                }
+            }
+        }
+    }
+
+    // Lookups on the indexes with all results of query, if exists. On
+    // the AND we choose the term with less files
+    fn lookups(&self, catalog: &Catalog) -> Option<Vec<Lookup>> {
+        match *self {
+            Query::And(ref queries) => {
+                queries.iter()
+                    .filter_map(|query| query.lookups(catalog))
+                    .min_by_key(|lookups| lookups.iter().map(|lookup| catalog.index.estimate(lookup)).sum::<usize>())
+            },
+            Query::Or(ref queries) => {
+                let mut lookups = vec![];
+
+                for query in queries {
+                    lookups.extend(query.lookups(catalog)?);
+                }
+
+                Some(lookups)
+            },
+            Query::Not(_) => None,
+            Query::Term(ref term) => term.lookup().map(|lookup| vec![lookup])
+        }
+    }
+
+    // Split the text on words, quoted texts and parentheses
+    fn tokenize(text: &str) -> Result<Vec<Token>, String> {
+
+        let mut tokens = vec![];
+        let mut chars = text.chars().peekable();
+
+        while let Some(c) = chars.next() {
+            match c {
+                ' ' | '\t' | '\r' | '\n' => {},
+                '(' => tokens.push(Token::Open),
+                ')' => tokens.push(Token::Close),
+                '"' => {
+                    let mut quoted = String::new();
+                    let mut closed = false;
+
+                    while let Some(c) = chars.next() {
+                        if c == '"' {
+                            closed = true;
+                            break;
+                        }
+                        quoted.push(c);
+                    }
+
+                    if !closed {
+                        return Err("Unclosed '\"'".to_string());
+                    }
+
+                    tokens.push(Token::Quoted(quoted));
+                },
+                _ => {
+                    let mut word = c.to_string();
+
+                    while let Some(&c) = chars.peek() {
+                        if c.is_whitespace() || c == '(' || c == ')' {
+                            break;
+                        }
+                        word.push(c);
+                        chars.next();
+                    }
+
+                    tokens.push(Token::Word(word));
+                }
+            }
+        }
+
+        Ok(tokens)
+    }
+
+    fn parse_or(tokens: &[Token], pos: &mut usize) -> Result<Query, String> {
+
+        let mut queries = vec![Query::parse_and(tokens, pos)?];
+
+        while *pos < tokens.len() && tokens[*pos] == Token::Word("OR".to_string()) {
+            *pos = *pos + 1;
+            queries.push(Query::parse_and(tokens, pos)?);
+        }
+
+        if queries.len() == 1 {
+            return Ok(queries.remove(0));
+        }
+
+        Ok(Query::Or(queries))
+    }
+
+    fn parse_and(tokens: &[Token], pos: &mut usize) -> Result<Query, String> {
+
+        let mut queries = vec![];
+
+        while *pos < tokens.len() {
+            match tokens[*pos] {
+                Token::Close => break,
+                Token::Word(ref word) if word == "OR" => break,
+                Token::Word(ref word) if word == "AND" => {
+                    *pos = *pos + 1;
+                },
+                _ => queries.push(Query::parse_not(tokens, pos)?)
+            }
+        }
+
+        if queries.is_empty() {
+            return Err("Expected a term".to_string());
+        }
+
+        if queries.len() == 1 {
+            return Ok(queries.remove(0));
+        }
+
+        Ok(Query::And(queries))
+    }
+
+    fn parse_not(tokens: &[Token], pos: &mut usize) -> Result<Query, String> {
+
+        if *pos >= tokens.len() {
+            return Err("Expected a term".to_string());
+        }
+
+        match tokens[*pos] {
+            Token::Word(ref word) if word == "NOT" => {
+                *pos = *pos + 1;
+                Ok(Query::Not(Box::new(Query::parse_not(tokens, pos)?)))
+            },
+            Token::Word(ref word) if word.len() > 1 && word.starts_with('-') => {
+                *pos = *pos + 1;
+                Ok(Query::Not(Box::new(Query::Term(Term::parse(&word[1..])?))))
+            },
+            Token::Open => {
+                *pos = *pos + 1;
+
+                let query = Query::parse_or(tokens, pos)?;
+
+                if *pos >= tokens.len() || tokens[*pos] != Token::Close {
+                    return Err("Expected ')'".to_string());
+                }
+
+                *pos = *pos + 1;
+
+                Ok(query)
+            },
+            Token::Close => Err("Unexpected ')'".to_string()),
+            Token::Quoted(ref quoted) => {
+                *pos = *pos + 1;
+                Ok(Query::Term(Term::Name(quoted.to_lowercase())))
+            },
+            Token::Word(ref word) => {
+                *pos = *pos + 1;
+                Ok(Query::Term(Term::parse(word)?))
+            }
+        }
+    }
+}
+
+impl Term {
+
+    // Parse a single term, like ext:pdf, size>1M or *.png
+    fn parse(word: &str) -> Result<Term, String> {
+
+        if word.starts_with("ext:") {
+            return Ok(Term::Ext(word[4..].trim_start_matches('.').to_lowercase()));
+        }
+
+        if word.starts_with("archive:") {
+            return Ok(Term::Archive(Term::glob(&word[8..])?));
+        }
+
+        if word.starts_with("path:") {
+            return Ok(Term::Path(word[5..].trim_start_matches('/').to_lowercase()));
+        }
+
+        if word.starts_with("re:") {
+            let regex = RegexBuilder::new(&word[3..])
+                .case_insensitive(true)
+                .build()
+                .map_err(|e| format!("Invalid regex {}: {}", &word[3..], e))?;
+
+            return Ok(Term::Regex(regex));
+        }
+
+        if word.starts_with("size") {
+            if let Some((cmp, value)) = Term::comparison(&word[4..]) {
+                return Ok(Term::Size(cmp, Term::size(value)?));
+            }
+        }
+
+        if word.starts_with("mtime") {
+            if let Some((cmp, value)) = Term::comparison(&word[5..]) {
+                return Ok(Term::Mtime(cmp, Term::date(value)?));
+            }
+        }
+
+        if word.contains('*') || word.contains('?') || word.contains('[') {
+            if word.contains('/') {
+                return Ok(Term::PathGlob(Term::glob(word)?));
+            }
+
+            return Ok(Term::Glob(Term::glob(word)?));
+        }
+
+        Ok(Term::Name(word.to_lowercase()))
+    }
+
+    // Lookup on the indexes with all files of the term
+    fn lookup(&self) -> Option<Lookup> {
+        match *self {
+            Term::Name(ref name) if !name.contains('/') && name.chars().count() >= 3 => {
+                Some(Lookup::Name(name.clone()))
+            },
+            Term::Glob(ref pattern) => Term::glob_lookup(&pattern.as_str().to_lowercase()),
+            Term::Ext(ref ext) => Some(Lookup::Ext(ext.clone())),
+            _ => None
+        }
+    }
+
+    // Lookup by the literal parts of the glob, the start of the name
+    // or the longest part with 3 chars or more
+    fn glob_lookup(pattern: &str) -> Option<Lookup> {
+
+        let mut literals = vec![String::new()];
+        let mut chars = pattern.chars();
+
+        while let Some(c) = chars.next() {
+            match c {
+                '*' | '?' => literals.push(String::new()),
+                '[' => {
+                    // Class of chars, like [a-z]
+                    while let Some(c) = chars.next() {
+                        if c == ']' {
+                            break;
+                        }
+                    }
+
+                    literals.push(String::new());
+                },
+                _ => {
+                    if let Some(literal) = literals.last_mut() {
+                        literal.push(c);
+                    }
+                }
+            }
+        }
+
+        let prefix = literals[0].clone();
+
+        if prefix.chars().count() >= 3 {
+            return Some(Lookup::NamePrefix(prefix));
+        }
+
+        let longest = literals.into_iter()
+            .max_by_key(|literal| literal.chars().count())
+            .unwrap_or_default();
+
+        if longest.chars().count() >= 3 {
+            return Some(Lookup::Name(longest));
+        }
+
+        if !prefix.is_empty() {
+            return Some(Lookup::NamePrefix(prefix));
+        }
+
+        None
+    }
+
+    fn matches(&self, tar: &FileTar, file: &File) -> bool {
+
+        let options = MatchOptions {
+            case_sensitive: false,
+            require_literal_separator: false,
+            require_literal_leading_dot: false
+        };
+
+        match *self {
+            Term::Name(ref name) => {
+                if name.contains('/') {
+                    return file.full_path.to_lowercase().contains(name.as_str());
+                }
+
+                file.file_name.to_lowercase().contains(name.as_str())
+            },
+            Term::Glob(ref pattern) => pattern.matches_with(&file.file_name, options),
+            Term::PathGlob(ref pattern) => pattern.matches_with(&file.full_path, options),
+            Term::Regex(ref regex) => regex.is_match(&file.full_path),
+            Term::Ext(ref ext) => {
+                Path::new(&file.file_name).extension()
+                    .map(|fext| fext.to_string_lossy().to_lowercase() == *ext)
+                    .unwrap_or(false)
+            },
+            Term::Archive(ref pattern) => pattern.matches_with(&tar.file_name, options),
+            Term::Path(ref prefix) => file.full_path.to_lowercase().starts_with(prefix.as_str()),
+            Term::Size(cmp, size) => cmp.compare(file.size, size),
+            Term::Mtime(cmp, mtime) => cmp.compare(file.mtime, mtime)
+        }
+    }
+
+    fn glob(text: &str) -> Result<Pattern, String> {
+        Pattern::new(text)
+            .map_err(|e| format!("Invalid glob {}: {}", text, e))
+    }
+
+    // Split the operator and the value, like >=1M
+    fn comparison(text: &str) -> Option<(Cmp, &str)> {
+
+        let operators = [(">=", Cmp::Ge), ("<=", Cmp::Le), (">", Cmp::Gt), ("<", Cmp::Lt), ("=", Cmp::Eq), (":", Cmp::Eq)];
+
+        for &(operator, cmp) in operators.iter() {
+            if text.starts_with(operator) {
+                return Some((cmp, &text[operator.len()..]));
+            }
+        }
+
+        None
+    }
+
+    // Bytes of the size, like 100, 10K, 1.5M or 2G
+    fn size(text: &str) -> Result<u64, String> {
+
+        let upper = text.to_uppercase();
+        let number = upper.trim_end_matches('B');
+
+        let (number, unit) = match number.chars().last() {
+            Some('K') => (&number[..number.len() - 1], 1u64 << 10),
+            Some('M') => (&number[..number.len() - 1], 1u64 << 20),
+            Some('G') => (&number[..number.len() - 1], 1u64 << 30),
+            Some('T') => (&number[..number.len() - 1], 1u64 << 40),
+            _ => (number, 1)
+        };
+
+        number.parse::<f64>()
+            .map(|number| (number * unit as f64) as u64)
+            .map_err(|_| format!("Invalid size {}", text))
+    }
+
+    // Unix time of the date(YYYY-MM-DD, on UTC) or of the timestamp
+    fn date(text: &str) -> Result<u64, String> {
+
+        if let Ok(timestamp) = text.parse::<u64>() {
+            return Ok(timestamp);
+        }
+
+        time::strptime(text, "%Y-%m-%d")
+            .map(|tm| tm.to_timespec().sec.max(0) as u64)
+            .map_err(|_| format!("Invalid date {}", text))
+    }
+}
+
+impl Cmp {
+
+    fn compare(&self, value: u64, other: u64) -> bool {
+        match *self {
+            Cmp::Lt => value < other,
+            Cmp::Le => value <= other,
+            Cmp::Eq => value == other,
+            Cmp::Ge => value >= other,
+            Cmp::Gt => value > other
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use std::path::Path;
+
+    use catalog::file::{File, FileTar, FileKind};
+
+    use super::Query;
+
+    fn file(full_path: &str, size: u64, mtime: u64) -> File {
+
+        let mut file = File::new_folder(full_path.to_string(), 2);
+
+        file.file_name = full_path.rsplit('/').next().unwrap().to_string();
+        file.size = size;
+        file.mtime = mtime;
+        file.is_file = true;
+        file.kind = FileKind::Regular;
+
+        file
+    }
+
+    fn matches(text: &str, full_path: &str) -> bool {
+        matches_file(text, &file(full_path, 0, 0))
+    }
+
+    fn matches_file(text: &str, file: &File) -> bool {
+
+        let tar = FileTar::from_path(Path::new("/backups/2019-photos.tar.gz"));
+
+        Query::parse(text)
+            .expect("The query is invalid")
+            .matches(&tar, file)
+    }
+
+    #[test]
+    fn and_before_or() {
+
+        match Query::parse("report final OR draft").unwrap() {
+            Query::Or(ref queries) => {
+                assert_eq!(queries.len(), 2);
+
+                match queries[0] {
+                    Query::And(ref queries) => assert_eq!(queries.len(), 2),
+                    ref other => panic!("Expected a AND, not {:?}", other)
+                }
+            },
+            other => panic!("Expected a OR, not {:?}", other)
+        }
+
+        assert!(matches("report final OR draft", "docs/draft.txt"));
+        assert!(matches("report final OR draft", "docs/report_final.txt"));
+        assert!(!matches("report final OR draft", "docs/report.txt"));
+
+        // The parentheses change the precedence
+        assert!(!matches("report (final OR draft)", "docs/draft.txt"));
+        assert!(matches("report (final OR draft)", "docs/report_draft.txt"));
+
+        // The AND is the default
+        assert_eq!(matches("report AND final", "report.txt"), matches("report final", "report.txt"));
+    }
+
+    #[test]
+    fn quoted_names() {
+        assert!(matches("\"my photo\"", "a/My Photo.png"));
+        assert!(!matches("\"my photo\"", "a/my-photo.png"));
+        assert!(!matches("my photo", "a/photo.png"));
+
+        // The quoted text is not a operator
+        assert!(matches("\"OR\"", "a/color.txt"));
+    }
+
+    #[test]
+    fn sizes() {
+
+        let small = file("a/small.bin", 1000, 0);
+        let big = file("a/big.bin", 3 * 1048576, 0);
+
+        assert!(matches_file("size<1K", &small));
+        assert!(!matches_file("size<1K", &big));
+        assert!(matches_file("size>1M", &big));
+        assert!(!matches_file("size>1M", &small));
+        assert!(matches_file("size>=1.5MB", &big));
+        assert!(matches_file("size=1000", &small));
+        assert!(matches_file("size:1000", &small));
+        assert!(!matches_file("size<=999", &small));
+    }
+
+    #[test]
+    fn extensions_and_others() {
+        assert!(matches("ext:pdf", "docs/a.PDF"));
+        assert!(matches("ext:.pdf", "docs/a.pdf"));
+        assert!(!matches("ext:pdf", "docs/pdf.txt"));
+
+        assert!(matches("*.png", "a/b.PNG"));
+        assert!(matches("a/*.png", "a/b.png"));
+        assert!(!matches("b/*.png", "a/b.png"));
+        assert!(matches("path:docs/2019", "docs/2019/a.txt"));
+        assert!(!matches("path:2019", "docs/2019/a.txt"));
+        assert!(matches("re:report_\\d+", "docs/report_12.pdf"));
+        assert!(matches("archive:2019-*", "a.txt"));
+        assert!(!matches("archive:2018-*", "a.txt"));
+
+        let old = file("a.txt", 0, 1000);
+
+        assert!(matches_file("mtime<2019-01-01", &old));
+        assert!(!matches_file("mtime>1000", &old));
+        assert!(matches_file("mtime>=1000", &old));
+    }
+
+    #[test]
+    fn negation() {
+        assert!(matches("-tmp", "docs/a.txt"));
+        assert!(!matches("-tmp", "docs/a.tmp"));
+        assert!(!matches("NOT tmp", "docs/a.tmp"));
+        assert!(!matches("-tmp/", "tmp/a.txt"));
+        assert!(matches("report -draft", "report.txt"));
+        assert!(!matches("report -draft", "report_draft.txt"));
+        assert!(matches("NOT (a.txt OR b.txt)", "c.txt"));
+        assert!(!matches("NOT NOT c.txt", "a.txt"));
+
+        // A single '-' is a name
+        assert!(matches("-", "a-b.txt"));
+    }
+
+    #[test]
+    fn malformed() {
+        for text in &["", "  ", "(", "(report", "report)", "()", "OR", "report OR", "NOT", "\"report", "size>abc", "mtime>2019-13-45x", "re:(", "archive:[a"] {
+            assert!(Query::parse(text).is_err(), "The query {:?} is valid", text);
+        }
+    }
+}
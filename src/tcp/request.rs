@@ -7,9 +7,13 @@
 ///
 
 use std::net::{TcpStream, SocketAddr};
-use std::io::{BufReader, BufRead, Write, BufWriter, copy};
+use std::io::{BufReader, BufRead, Read, Write, BufWriter, copy};
 
 use catalog::catalog::Catalog;
+use catalog::file::File;
+use tcp::command::{Command, Item, Failure};
+use tcp::v2::{V2, Reply};
+use tcp::pool::Sessions;
 
 pub struct Request {
 }
@@ -17,7 +21,7 @@ pub struct Request {
 impl Request {
 
     // Handle the client connection
-    pub fn handle(conn: TcpStream, catalog: &mut Catalog) {
+    pub fn handle(conn: TcpStream, catalog: &Catalog, sessions: &Sessions) {
         let pa = conn.peer_addr();
         if pa.is_err() {
             error!("Error on get the remote addr {:?}", conn);
@@ -36,81 +40,213 @@ impl Request {
             return;
         }
 
-        let mut command_ok = false;
+        // Many commands on the same connection, until the /quit
+        if command.trim() == "/session" {
 
-        if command.starts_with("/search/") {
+            // The sessions can't use all the workers
+            let _session = match sessions.start() {
+                Some(session) => session,
+                None => {
+                    warn!("Too many sessions, rejecting {}", client);
+                    V2::send(&conn, Reply::Error(503, "too_many_sessions", format!("All the {} sessions are in use", sessions.max())));
+                    return;
+                }
+            };
+
+            Request::session(&conn, client, buf_reader, catalog);
+            return;
+        }
 
-            let mut search = command.replacen("/search/", "", 1);
-            search = search.trim().to_string();
+        // The version 2 of the protocol, with the status on the responses
+        if command.starts_with("/v2/") {
 
-            if !search.is_empty() {
+            if V2::handle(&conn, &command["/v2".len()..], catalog) {
+                info!("Handling {}...OK", client);
+            }
 
-                for tar in catalog.get_catalogs() {
+            return;
+        }
+
+        let command = match Command::parse(&command) {
+            Ok(command) => command,
+            Err(Failure::InvalidCommand(_)) => {
+                warn!("Invalid command {}", client);
+                Request::response(&conn, client, "Invalid command\n".to_string());
+                return;
+            },
+            Err(failure) => {
+                Request::response(&conn, client, format!("{}\n", failure.message()));
+                return;
+            }
+        };
+
+        let mut count = 0;
+        let mut wasted_total = 0;
+
+        // The results are sent while found
+        let download = command.run(catalog, |item| {
+
+            count = count + 1;
+
+            if let Item::Duplicate(_, size, files) = item {
+                wasted_total = wasted_total + size * (files.len() as u64 - 1);
+            }
 
-                    for file in catalog.get_catalog(&tar) {
+            Request::response(&conn, client, Request::line(&command, item))
+        });
 
-                        if file.file_name.contains(search.as_str()) {
-                            Request::response(&conn, client, format!("{}:{}\n", tar.file_name.clone(), file.full_path.clone()));
-                        }
+        match download {
+            Ok(Some(download)) => {
+                if copy(&mut download.content.take(download.length), &mut BufWriter::new(&conn)).is_err() {
+                    error!("Error on write on buffer: {}", download.file.full_path);
+                    return;
+                }
+            },
+            Ok(None) => {
+                match command {
+                    Command::Verify(_) if count == 0 => {
+                        Request::response(&conn, client, "OK\n".to_string());
+                    },
+                    Command::Duplicates => {
+                        Request::response(&conn, client, format!("wasted: {}\n", wasted_total));
+                    },
+                    _ => {}
+                }
+            },
+            Err(failure) => {
+                match command {
+                    // The content of downloads is binary, so the errors
+                    // are only logged
+                    Command::Download(_) | Command::DownloadRange(..) | Command::DownloadByHash(_) => {
+                        error!("{}", failure.message());
+                        return;
+                    },
+                    _ => {
+                        Request::response(&conn, client, format!("{}\n", failure.message()));
                     }
                 }
-
-                command_ok = true;
             }
+        }
+
+        info!("Handling {}...OK", client);
+    }
 
-        } else if command.starts_with("/download/") && command.contains(":") {
+    // Read and run the commands of the client, in order, until the /quit
+    // or the end of connection. The commands can be sent without wait
+    // for the responses, and they are answered like on the version 2
+    fn session(conn: &TcpStream, client: SocketAddr, mut reader: BufReader<&TcpStream>, catalog: &Catalog) {
 
-            let mut download = command.replacen("/download/", "", 1);
-            download = download.trim().to_string();
-            if !download.is_empty() {
-                let mut download_slices = download.split(":");
+        info!("Starting the session of {}...", client);
 
-                let tar_file = download_slices.next();
-                if tar_file.is_none() {
-                    error!("Tar file not setted: {}", download);
-                    return;
+        // The client knows that the sessions are supported
+        if !V2::send(conn, Reply::End(0)) {
+            return;
+        }
+
+        let mut handled = 0;
+
+        loop {
+            let mut command = String::new();
+
+            match reader.read_line(&mut command) {
+                Ok(0) => break,
+                Ok(_) => {},
+                Err(e) => {
+                    warn!("Error on read the command from the client {}: {}", client, e);
+                    break;
                 }
+            }
 
-                let name_file = download_slices.next();
-                if name_file.is_none() {
-                    error!("Name of file not setted: {}", download);
-                    return;
+            let command = command.trim();
+
+            if command.is_empty() {
+                continue;
+            }
+
+            if command == "/quit" {
+                V2::send(conn, Reply::End(0));
+                break;
+            }
+
+            let command = if command.starts_with("/v2/") {
+                &command["/v2".len()..]
+            } else {
+                command
+            };
+
+            handled = handled + 1;
+
+            // After a incomplete response the client is lost
+            if !V2::handle(conn, command, catalog) {
+                break;
+            }
+        }
+
+        info!("Ending the session of {} after {} commands", client, handled);
+    }
+
+    // Text line of a result of the listings
+    fn line(command: &Command, item: Item) -> String {
+        match item {
+            Item::File(tar, file) => {
+                match *command {
+                    Command::Stat(_) => Request::stat(file),
+                    Command::Ls(_) => {
+                        // One line per file: type, size, mtime and name. The
+                        // names of folders end with '/'
+                        let kind = if file.is_archive { "archive" } else { file.kind.name() };
+                        let slash = if file.is_file || file.file_name.ends_with('/') { "" } else { "/" };
+
+                        format!("{}\t{}\t{}\t{}{}\n", kind, file.size, file.mtime, file.file_name, slash)
+                    },
+                    _ => format!("{}:{}\n", tar.file_name, file.full_path)
                 }
+            },
+            Item::Scored(score, tar, file) => format!("{:.2}\t{}:{}\n", score, tar.file_name, file.full_path),
+            Item::Line(tar, file, number, text) => format!("{}:{}:{}:{}\n", tar.file_name, file.full_path, number, text),
+            Item::Archive(tar) => format!("{}\n", tar.file_name),
+            Item::Problem(tar, full_path, problem) => format!("{}:{}: {}\n", tar.file_name, full_path, problem),
+            Item::Duplicate(sha256, size, files) => {
 
-                for tar in catalog.get_catalogs() {
-
-                    if tar.file_name == tar_file.unwrap() {
-
-                        for file in catalog.get_catalog(&tar) {
-
-                            if file.full_path == name_file.unwrap() {
-                                if let Some(extracted) = catalog.extract_file(&tar, &file) {
-                                    if copy(&mut BufReader::new(extracted), &mut BufWriter::new(&conn)).is_err() {
-                                        error!("Error on write on buffer: {}", download);
-                                        return;
-                                    }
-                                } else {
-                                    error!("Error on extract: {}", download);
-                                    return;
-                                }
-                                break;
-                            }
-                        }
-                        break;
-                    }
+                let mut text = format!("{} size: {} copies: {} wasted: {}\n", sha256, size, files.len(), size * (files.len() as u64 - 1));
+
+                for &(ref tar, ref full_path) in files {
+                    text.push_str(&format!("  {}:{}\n", tar.file_name, full_path));
                 }
 
-                command_ok = true;
-            }
+                text
+            },
+            Item::CacheStats(stats) => format!("entries: {}\nsize: {}\nmax size: {}\nhits: {}\nmisses: {}\nevictions: {}\n",
+                stats.entries, stats.size, stats.max_size, stats.hits, stats.misses, stats.evictions)
         }
+    }
 
-        if !command_ok {
-            warn!("Invalid command {}", client);
-            Request::response(&conn, client, "Invalid command\n".to_string());
-            return;
+    // Metadata of the indexed file, one field per line
+    fn stat(file: &File) -> String {
+
+        let mut text = String::new();
+
+        text.push_str(&format!("path: {}\n", file.full_path));
+        text.push_str(&format!("type: {}\n", file.kind.name()));
+        text.push_str(&format!("size: {}\n", file.size));
+        text.push_str(&format!("mtime: {}\n", file.mtime));
+        text.push_str(&format!("mode: {:04o}\n", file.mode));
+        text.push_str(&format!("uid: {} ({})\n", file.uid, file.user_name.clone().unwrap_or_default()));
+        text.push_str(&format!("gid: {} ({})\n", file.gid, file.group_name.clone().unwrap_or_default()));
+
+        if let Some(ref link_name) = file.link_name {
+            text.push_str(&format!("link: {}\n", link_name));
         }
 
-        info!("Handling {}...OK", client);
+        if let (Some(major), Some(minor)) = (file.device_major, file.device_minor) {
+            text.push_str(&format!("device: {},{}\n", major, minor));
+        }
+
+        if let Some(ref sha256) = file.sha256 {
+            text.push_str(&format!("sha256: {}\n", sha256));
+        }
+
+        text
     }
 
     // Create and flush the response to the client
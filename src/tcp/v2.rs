@@ -0,0 +1,192 @@
+///
+/// Blitz Explorer
+///
+/// Version 2 of the tcp protocol, with the commands prefixed by /v2/.
+/// Each response has a JSON line with his status. The listings send
+/// his JSON lines while found and the status, with the count of them,
+/// at the end. The downloads start with the status, followed by his
+/// length of bytes, and the errors are only the status
+///
+/// Copyright 2019 Luis Fernando Batels <luisfbatels@gmail.com>
+///
+
+use std::net::TcpStream;
+use std::io::{Read, Write, BufWriter, copy};
+
+use serde_json::{self, Value};
+
+use catalog::catalog::Catalog;
+use tcp::command::{Command, Item, Download, Failure};
+
+pub enum Reply {
+    End(usize), // end of the listing, with the count of lines sent
+    Content(Value, u64, Box<dyn Read>), // fields of the status line, length and content
+    Error(u16, &'static str, String) // status, kind and message
+}
+
+pub struct V2 {
+}
+
+impl V2 {
+
+    // Run the command, without the /v2 prefix, and send his response.
+    // Returns false when the connection can't be used anymore
+    pub fn handle(conn: &TcpStream, line: &str, catalog: &Catalog) -> bool {
+
+        let command = match Command::parse(line) {
+            Ok(command) => command,
+            Err(failure) => return V2::send(conn, V2::error(failure))
+        };
+
+        let mut writer = BufWriter::new(conn);
+        let mut count = 0;
+        let mut sent = true;
+
+        // The lines are sent while found, without keep them
+        let download = command.run(catalog, |item| {
+
+            let mut line = V2::line(item).to_string();
+            line.push('\n');
+
+            sent = writer.write_all(line.as_bytes()).is_ok();
+            count = count + 1;
+
+            sent
+        });
+
+        if !sent || writer.flush().is_err() {
+            error!("Error on send the response to client {:?}", conn.peer_addr());
+            return false;
+        }
+
+        let reply = match download {
+            Ok(Some(download)) => V2::content(&command, download),
+            Ok(None) => Reply::End(count),
+            Err(failure) => V2::error(failure)
+        };
+
+        V2::send(conn, reply)
+    }
+
+    // Write the response. The content of downloads is sent complete,
+    // or the connection is lost
+    pub fn send(conn: &TcpStream, reply: Reply) -> bool {
+
+        let mut writer = BufWriter::new(conn);
+
+        let sent = match reply {
+            Reply::End(count) => {
+                let line = V2::status_line(json!({ "status": 200, "count": count }));
+
+                writer.write_all(line.as_bytes()).is_ok()
+            },
+            Reply::Content(mut fields, length, content) => {
+                fields["status"] = json!(200);
+                fields["length"] = json!(length);
+
+                if writer.write_all(V2::status_line(fields).as_bytes()).is_err() {
+                    false
+                } else {
+                    match copy(&mut content.take(length), &mut writer) {
+                        Ok(written) if written == length => true,
+                        Ok(written) => {
+                            error!("Incomplete content: {} of {} bytes", written, length);
+                            false
+                        },
+                        Err(_) => false
+                    }
+                }
+            },
+            Reply::Error(status, kind, message) => {
+                let line = V2::status_line(json!({ "status": status, "error": kind, "message": message }));
+
+                writer.write_all(line.as_bytes()).is_ok()
+            }
+        };
+
+        if !sent || writer.flush().is_err() {
+            error!("Error on send the response to client {:?}", conn.peer_addr());
+            return false;
+        }
+
+        true
+    }
+
+    // JSON line of a result of the listings
+    fn line(item: Item) -> Value {
+        match item {
+            Item::File(tar, file) => file.to_json(tar),
+            Item::Scored(score, tar, file) => {
+                let mut line = file.to_json(tar);
+                line["score"] = json!(score);
+                line
+            },
+            Item::Line(tar, file, number, text) => json!({
+                "archive": tar.file_name,
+                "path": file.full_path,
+                "line": number,
+                "text": text
+            }),
+            Item::Archive(tar) => json!({ "name": tar.file_name, "path": tar.full_path }),
+            Item::Problem(tar, full_path, problem) => json!({ "archive": tar.file_name, "path": full_path, "problem": problem }),
+            Item::Duplicate(sha256, size, files) => {
+                let copies: Vec<Value> = files.iter()
+                    .map(|&(ref tar, ref full_path)| json!({ "archive": tar.file_name, "path": full_path }))
+                    .collect();
+
+                json!({
+                    "sha256": sha256,
+                    "size": size,
+                    "copies": copies.len(),
+                    "wasted": size * (copies.len() as u64 - 1),
+                    "files": copies
+                })
+            },
+            Item::CacheStats(stats) => json!({
+                "entries": stats.entries,
+                "size": stats.size,
+                "max_size": stats.max_size,
+                "hits": stats.hits,
+                "misses": stats.misses,
+                "evictions": stats.evictions
+            })
+        }
+    }
+
+    // The status line of the downloads has what was downloaded
+    fn content(command: &Command, download: Download) -> Reply {
+
+        let fields = match *command {
+            Command::DownloadRange(..) => json!({ "path": download.file.full_path, "start": download.start, "size": download.file.size }),
+            Command::DownloadByHash(ref sha256) => json!({ "sha256": sha256 }),
+            _ => json!({ "path": download.file.full_path })
+        };
+
+        Reply::Content(fields, download.length, download.content)
+    }
+
+    fn error(failure: Failure) -> Reply {
+
+        let (status, kind) = match failure {
+            Failure::InvalidCommand(_) => (400, "invalid_command"),
+            Failure::InvalidQuery(_) => (400, "invalid_query"),
+            Failure::NotAFile(_) => (400, "not_a_file"),
+            Failure::NotADirectory(_) => (400, "not_a_directory"),
+            Failure::UnknownArchive(_) => (404, "unknown_archive"),
+            Failure::UnknownPath(..) => (404, "unknown_path"),
+            Failure::UnknownHash(_) => (404, "unknown_hash"),
+            Failure::InvalidRange(_) => (416, "invalid_range"),
+            Failure::ExtractionFailed(_) => (500, "extraction_failed")
+        };
+
+        if status == 500 {
+            error!("{}", failure.message());
+        }
+
+        Reply::Error(status, kind, failure.message())
+    }
+
+    fn status_line(fields: Value) -> String {
+        format!("{}\n", serde_json::to_string(&fields).expect("Error on Serialize the status"))
+    }
+}